@@ -0,0 +1,6 @@
+ALTER TABLE programs
+ADD slug VARCHAR(255) UNIQUE;
+
+-- Programs that have a scraper in the `scraper` crate.
+UPDATE programs SET slug = 'dormys' WHERE id = 147;
+UPDATE programs SET slug = 'cocoweb' WHERE id = 148;
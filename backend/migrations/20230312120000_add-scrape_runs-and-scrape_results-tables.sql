@@ -0,0 +1,29 @@
+CREATE TYPE scrape_failure AS ENUM (
+    'bad_credentials',
+    'layout_changed',
+    'unknown_status',
+    'timeout',
+    'other'
+);
+
+CREATE TABLE IF NOT EXISTS scrape_runs (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+    succeeded INT NOT NULL DEFAULT 0,
+    failed INT NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS scrape_results (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    run_id INT NOT NULL,
+    user_pubkey BYTEA NOT NULL,
+    program_id INT NOT NULL,
+    status VARCHAR(255),
+    failure scrape_failure,
+    message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (run_id) REFERENCES scrape_runs(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_pubkey) REFERENCES users(pubkey) ON DELETE CASCADE,
+    FOREIGN KEY (program_id) REFERENCES programs(id) ON DELETE CASCADE
+);
@@ -0,0 +1,10 @@
+-- Passwords are sealed with a per-row data key, which is sealed with the
+-- master key of `key_version`. Rows that still have a plaintext `password`
+-- are encrypted by `scraper rekey`.
+ALTER TABLE user_credentials
+ALTER COLUMN password DROP NOT NULL;
+
+ALTER TABLE user_credentials
+ADD password_ciphertext BYTEA,
+ADD data_key BYTEA,
+ADD key_version INT;
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS scrape_jobs (
+    user_pubkey BYTEA NOT NULL,
+    program_id INT NOT NULL,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    attempts INT NOT NULL DEFAULT 0,
+    locked_until TIMESTAMPTZ,
+    last_error TEXT,
+    PRIMARY KEY (user_pubkey, program_id),
+    FOREIGN KEY (user_pubkey, program_id) REFERENCES user_credentials(user_pubkey, program_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS scrape_jobs_run_at_idx ON scrape_jobs (run_at);
+
+-- Results of the daemon do not belong to a run.
+ALTER TABLE scrape_results
+ALTER COLUMN run_id DROP NOT NULL;
@@ -0,0 +1,55 @@
+CREATE TYPE status_source AS ENUM ('scraper', 'manual');
+
+ALTER TABLE user_statuses
+ADD source status_source NOT NULL DEFAULT 'scraper',
+ADD updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE TABLE IF NOT EXISTS user_status_history (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_pubkey BYTEA NOT NULL,
+    program_id INT NOT NULL,
+    previous_level INT,
+    level INT NOT NULL,
+    source status_source NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_pubkey) REFERENCES users(pubkey) ON DELETE CASCADE,
+    FOREIGN KEY (program_id) REFERENCES programs(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS user_status_history_user_pubkey_idx
+ON user_status_history (user_pubkey, recorded_at);
+
+INSERT INTO user_status_history (user_pubkey, program_id, level, source)
+SELECT user_pubkey, program_id, level, source FROM user_statuses;
+
+-- Appends a row to user_status_history and notifies `status_changed`
+-- whenever the level of a user status changes, whoever writes it.
+CREATE OR REPLACE FUNCTION record_user_status_change() RETURNS TRIGGER AS $$
+DECLARE
+    previous_level INT;
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        IF OLD.level = NEW.level THEN
+            RETURN NEW;
+        END IF;
+        previous_level := OLD.level;
+    END IF;
+
+    INSERT INTO user_status_history (user_pubkey, program_id, previous_level, level, source)
+    VALUES (NEW.user_pubkey, NEW.program_id, previous_level, NEW.level, NEW.source);
+
+    PERFORM pg_notify('status_changed', json_build_object(
+        'user_pubkey', encode(NEW.user_pubkey, 'hex'),
+        'program_id', NEW.program_id,
+        'previous_level', previous_level,
+        'level', NEW.level,
+        'source', NEW.source
+    )::text);
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER user_statuses_history
+AFTER INSERT OR UPDATE ON user_statuses
+FOR EACH ROW EXECUTE FUNCTION record_user_status_change();
@@ -0,0 +1,10 @@
+-- The id of the report on statusmatcher.com. Reports stored before the sync
+-- was made idempotent have none.
+ALTER TABLE reports
+ADD source_id INT UNIQUE;
+
+CREATE TABLE IF NOT EXISTS sync_watermarks (
+    source VARCHAR(255) PRIMARY KEY,
+    last_source_id INT NOT NULL,
+    synced_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
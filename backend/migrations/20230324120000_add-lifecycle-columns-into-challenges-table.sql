@@ -0,0 +1,7 @@
+ALTER TABLE challenges
+ADD created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+ADD expires_at TIMESTAMPTZ NOT NULL DEFAULT NOW() + INTERVAL '5 minutes',
+-- Set when a token has been issued for the challenge.
+ADD consumed_at TIMESTAMPTZ;
+
+CREATE INDEX challenges_expires_at_idx ON challenges (expires_at);
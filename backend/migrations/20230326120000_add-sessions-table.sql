@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS sessions (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_pubkey BYTEA NOT NULL,
+    -- SHA-256 of the current refresh token, replaced on every refresh.
+    refresh_token_hash BYTEA NOT NULL UNIQUE,
+    -- `jti` of the only access token that is accepted for the session.
+    access_token_id VARCHAR(255) NOT NULL UNIQUE,
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    refreshed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    FOREIGN KEY (user_pubkey) REFERENCES users(pubkey) ON DELETE CASCADE
+);
+
+CREATE INDEX sessions_user_pubkey_idx ON sessions (user_pubkey);
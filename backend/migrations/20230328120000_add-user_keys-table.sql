@@ -0,0 +1,12 @@
+CREATE TYPE key_type AS ENUM ('lnurl', 'nostr');
+
+-- Keys that sign in to the account of `user_pubkey` besides its own.
+CREATE TABLE IF NOT EXISTS user_keys (
+    pubkey BYTEA PRIMARY KEY,
+    key_type key_type NOT NULL,
+    user_pubkey BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_pubkey) REFERENCES users(pubkey) ON DELETE CASCADE
+);
+
+CREATE INDEX user_keys_user_pubkey_idx ON user_keys (user_pubkey);
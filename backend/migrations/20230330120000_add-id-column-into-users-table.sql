@@ -0,0 +1,117 @@
+-- Accounts get a stable id so that keys can be added and removed without
+-- losing what belongs to the account.
+ALTER TABLE users
+ADD id INT GENERATED ALWAYS AS IDENTITY UNIQUE,
+ADD created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Linked keys point at the account by its id, and the key each account
+-- signed up with becomes one of its keys.
+ALTER TABLE user_keys ADD user_id INT;
+
+UPDATE user_keys SET user_id = users.id FROM users WHERE user_keys.user_pubkey = users.pubkey;
+
+ALTER TABLE user_keys DROP COLUMN user_pubkey;
+
+-- LNURL-auth keys are compressed secp256k1 keys, Nostr keys are x-only.
+INSERT INTO user_keys (pubkey, key_type, user_id)
+SELECT pubkey, CASE WHEN length(pubkey) = 32 THEN 'nostr' ELSE 'lnurl' END::key_type, id
+FROM users;
+
+ALTER TABLE user_keys
+ALTER COLUMN user_id SET NOT NULL,
+ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE INDEX user_keys_user_id_idx ON user_keys (user_id);
+
+-- Move every reference from the pubkey to the id.
+ALTER TABLE challenges ADD user_id INT;
+ALTER TABLE user_credentials ADD user_id INT;
+ALTER TABLE user_statuses ADD user_id INT;
+ALTER TABLE user_status_history ADD user_id INT;
+ALTER TABLE scrape_jobs ADD user_id INT;
+ALTER TABLE scrape_results ADD user_id INT;
+ALTER TABLE sessions ADD user_id INT;
+
+UPDATE challenges SET user_id = users.id FROM users WHERE challenges.user_pubkey = users.pubkey;
+UPDATE user_credentials SET user_id = users.id FROM users WHERE user_credentials.user_pubkey = users.pubkey;
+UPDATE user_statuses SET user_id = users.id FROM users WHERE user_statuses.user_pubkey = users.pubkey;
+UPDATE user_status_history SET user_id = users.id FROM users WHERE user_status_history.user_pubkey = users.pubkey;
+UPDATE scrape_jobs SET user_id = users.id FROM users WHERE scrape_jobs.user_pubkey = users.pubkey;
+UPDATE scrape_results SET user_id = users.id FROM users WHERE scrape_results.user_pubkey = users.pubkey;
+UPDATE sessions SET user_id = users.id FROM users WHERE sessions.user_pubkey = users.pubkey;
+
+-- Drops the primary keys and foreign keys built on the pubkeys along with them.
+ALTER TABLE scrape_jobs DROP COLUMN user_pubkey CASCADE;
+ALTER TABLE user_credentials DROP COLUMN user_pubkey CASCADE;
+ALTER TABLE user_statuses DROP COLUMN user_pubkey CASCADE;
+ALTER TABLE user_status_history DROP COLUMN user_pubkey CASCADE;
+ALTER TABLE scrape_results DROP COLUMN user_pubkey CASCADE;
+ALTER TABLE sessions DROP COLUMN user_pubkey CASCADE;
+ALTER TABLE challenges DROP COLUMN user_pubkey CASCADE;
+ALTER TABLE users DROP COLUMN pubkey CASCADE;
+
+ALTER TABLE users ADD PRIMARY KEY (id);
+
+ALTER TABLE user_credentials
+ALTER COLUMN user_id SET NOT NULL,
+ADD PRIMARY KEY (user_id, program_id),
+ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE user_statuses
+ALTER COLUMN user_id SET NOT NULL,
+ADD PRIMARY KEY (user_id, program_id),
+ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE user_status_history
+ALTER COLUMN user_id SET NOT NULL,
+ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS user_status_history_user_id_idx
+ON user_status_history (user_id, recorded_at);
+
+ALTER TABLE scrape_jobs
+ALTER COLUMN user_id SET NOT NULL,
+ADD PRIMARY KEY (user_id, program_id),
+ADD FOREIGN KEY (user_id, program_id) REFERENCES user_credentials(user_id, program_id) ON DELETE CASCADE;
+
+ALTER TABLE scrape_results
+ALTER COLUMN user_id SET NOT NULL,
+ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+ALTER TABLE sessions
+ALTER COLUMN user_id SET NOT NULL,
+ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+
+-- A challenge either signs in to `user_id` or, when `link_user_id` is set,
+-- adds the signing key to that account.
+ALTER TABLE challenges
+ADD FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+ADD link_user_id INT REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE OR REPLACE FUNCTION record_user_status_change() RETURNS TRIGGER AS $$
+DECLARE
+    previous_level INT;
+BEGIN
+    IF TG_OP = 'UPDATE' THEN
+        IF OLD.level = NEW.level THEN
+            RETURN NEW;
+        END IF;
+        previous_level := OLD.level;
+    END IF;
+
+    INSERT INTO user_status_history (user_id, program_id, previous_level, level, source)
+    VALUES (NEW.user_id, NEW.program_id, previous_level, NEW.level, NEW.source);
+
+    PERFORM pg_notify('status_changed', json_build_object(
+        'user_id', NEW.user_id,
+        'program_id', NEW.program_id,
+        'previous_level', previous_level,
+        'level', NEW.level,
+        'source', NEW.source
+    )::text);
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
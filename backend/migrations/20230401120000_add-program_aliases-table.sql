@@ -0,0 +1,16 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Other names that members search a program by, e.g. "Bonvoy".
+CREATE TABLE IF NOT EXISTS program_aliases (
+    program_id INT NOT NULL,
+    alias VARCHAR(255) NOT NULL,
+    PRIMARY KEY (program_id, alias),
+    FOREIGN KEY (program_id) REFERENCES programs(id) ON DELETE CASCADE
+);
+
+-- Serve both `%` (similarity) and ILIKE.
+CREATE INDEX IF NOT EXISTS programs_name_trgm_idx
+ON programs USING GIN (name gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS program_aliases_alias_trgm_idx
+ON program_aliases USING GIN (alias gin_trgm_ops);
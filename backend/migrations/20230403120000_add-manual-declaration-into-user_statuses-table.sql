@@ -0,0 +1,8 @@
+-- Statuses read by the scraper are verified, the ones users declare are not.
+ALTER TYPE status_source RENAME VALUE 'scraper' TO 'verified';
+
+ALTER TABLE user_statuses
+ADD expires_on DATE,
+-- The file under EVIDENCE_DIR that backs a manual declaration.
+ADD evidence_file VARCHAR(255),
+ADD evidence_content_type VARCHAR(255);
@@ -0,0 +1,11 @@
+CREATE TYPE report_state AS ENUM ('pending', 'approved', 'rejected');
+
+-- Imported reports have already been moderated on statusmatcher.com.
+ALTER TABLE reports
+ADD state report_state NOT NULL DEFAULT 'approved',
+-- Whoever submitted the report. Imported reports have none.
+ADD user_id INT REFERENCES users(id) ON DELETE SET NULL,
+-- `created_at` is when the match was attempted, which users may backdate.
+ADD submitted_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX reports_user_id_idx ON reports (user_id, submitted_at);
@@ -0,0 +1,17 @@
+CREATE TYPE user_role AS ENUM ('member', 'admin');
+
+-- Admins are appointed by hand: UPDATE users SET role = 'admin' WHERE id = ...
+ALTER TABLE users
+ADD role user_role NOT NULL DEFAULT 'member';
+
+-- Every change made through the admin API, along with what it changed.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id INT,
+    action VARCHAR(255) NOT NULL,
+    details JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
@@ -0,0 +1,104 @@
+CREATE TYPE challenge_state AS ENUM ('ongoing', 'completed', 'failed');
+
+CREATE TABLE IF NOT EXISTS status_challenges (
+    id INT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id INT NOT NULL,
+    -- The status the challenge was offered for.
+    from_program_id INT NOT NULL,
+    from_status_level INT NOT NULL,
+    to_program_id INT NOT NULL,
+    to_status_level INT NOT NULL,
+    required_nights INT,
+    required_points INT,
+    nights INT NOT NULL DEFAULT 0,
+    points INT NOT NULL DEFAULT 0,
+    deadline DATE NOT NULL,
+    state challenge_state NOT NULL DEFAULT 'ongoing',
+    -- Submitted once the challenge has completed or failed.
+    report_id INT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ,
+    CHECK (required_nights IS NOT NULL OR required_points IS NOT NULL),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (from_program_id, from_status_level) REFERENCES program_statuses(program_id, level),
+    FOREIGN KEY (to_program_id, to_status_level) REFERENCES program_statuses(program_id, level),
+    FOREIGN KEY (report_id) REFERENCES reports(id) ON DELETE SET NULL
+);
+
+CREATE INDEX status_challenges_user_id_idx ON status_challenges (user_id);
+
+CREATE INDEX status_challenges_deadline_idx ON status_challenges (deadline)
+WHERE state = 'ongoing';
+
+-- Finishes an ongoing challenge and submits it as a report for moderation.
+-- The program offered a challenge either way, so the outcome goes into the notes.
+CREATE OR REPLACE FUNCTION finish_status_challenge(
+    challenge_id INT,
+    outcome challenge_state
+) RETURNS VOID AS $$
+DECLARE
+    challenge status_challenges;
+    new_report_id INT;
+BEGIN
+    SELECT * INTO challenge
+    FROM status_challenges
+    WHERE id = challenge_id AND state = 'ongoing'
+    FOR UPDATE;
+
+    IF NOT FOUND THEN
+        RETURN;
+    END IF;
+
+    INSERT INTO reports (
+        from_program_id,
+        from_status_level,
+        to_program_id,
+        to_status_level,
+        result,
+        notes,
+        state,
+        user_id
+    ) VALUES (
+        challenge.from_program_id,
+        challenge.from_status_level,
+        challenge.to_program_id,
+        challenge.to_status_level,
+        'challenge',
+        CASE outcome
+            WHEN 'completed' THEN 'Completed the status challenge.'
+            ELSE 'Failed the status challenge by the deadline.'
+        END,
+        'pending',
+        challenge.user_id
+    )
+    RETURNING id INTO new_report_id;
+
+    UPDATE status_challenges
+    SET
+        state = outcome,
+        finished_at = NOW(),
+        report_id = new_report_id
+    WHERE id = challenge_id;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Completes the challenges of a user once the target level is reached,
+-- whether the scraper read it or the user declared it.
+CREATE OR REPLACE FUNCTION complete_status_challenges() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM finish_status_challenge(id, 'completed')
+    FROM status_challenges
+    WHERE
+        user_id = NEW.user_id
+        AND to_program_id = NEW.program_id
+        AND to_status_level <= NEW.level
+        AND state = 'ongoing'
+        AND deadline >= CURRENT_DATE;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER user_statuses_challenges
+AFTER INSERT OR UPDATE ON user_statuses
+FOR EACH ROW EXECUTE FUNCTION complete_status_challenges();
@@ -0,0 +1,7 @@
+-- Serial ids differ from one database to another, so the slugs given to
+-- programs 147 and 148 may have landed on other programs.
+UPDATE programs SET slug = NULL WHERE slug IN ('dormys', 'cocoweb');
+
+-- Programs that have a scraper in the `scraper` crate.
+UPDATE programs SET slug = 'dormys' WHERE name = 'Dormy Inn';
+UPDATE programs SET slug = 'cocoweb' WHERE name = 'COCO''S';
@@ -0,0 +1,8 @@
+-- Imported reports without a submission date are stored as undated rather
+-- than as the time of import. They are left out of "within the last N months"
+-- and weigh as much as a report one half-life old.
+ALTER TABLE reports ALTER COLUMN created_at DROP NOT NULL;
+
+-- The next sync fetches every report again, so that those imported as of the
+-- time of import become undated.
+DELETE FROM sync_watermarks WHERE source = 'statusmatcher';
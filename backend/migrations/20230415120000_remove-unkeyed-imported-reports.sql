@@ -0,0 +1,7 @@
+-- Reports imported before the sync was keyed by source_id cannot be told
+-- apart from the ones the sync inserts again, so they are removed and the
+-- next sync fetches every report with its source_id. Reports submitted by
+-- users are kept.
+DELETE FROM reports WHERE source_id IS NULL AND user_id IS NULL;
+
+DELETE FROM sync_watermarks WHERE source = 'statusmatcher';
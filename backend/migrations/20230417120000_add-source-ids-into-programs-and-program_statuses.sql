@@ -0,0 +1,14 @@
+-- The ids of programs and statuses on statusmatcher.com, so that the sync
+-- finds them after an admin renames or merges them. Existing rows get theirs
+-- on the next sync, matched by name and level.
+ALTER TABLE programs
+ADD source_id INT UNIQUE;
+
+-- A status that was merged keeps pointing to the level it was merged into,
+-- which is why several source ids may share a level.
+CREATE TABLE IF NOT EXISTS program_status_sources (
+    source_id INT PRIMARY KEY,
+    program_id INT NOT NULL,
+    level INT NOT NULL,
+    FOREIGN KEY (program_id, level) REFERENCES program_statuses(program_id, level) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+-- SHA-256 of refresh tokens that have been replaced. One of them coming back
+-- means that it leaked, so the session it belonged to is revoked.
+CREATE TABLE IF NOT EXISTS rotated_refresh_tokens (
+    refresh_token_hash BYTEA PRIMARY KEY,
+    session_id INT NOT NULL,
+    FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+);
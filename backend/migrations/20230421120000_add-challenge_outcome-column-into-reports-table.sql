@@ -0,0 +1,61 @@
+-- How the status challenge behind a report ended, for reports submitted when
+-- a challenge finished. The notes only describe it to moderators.
+ALTER TABLE reports
+ADD challenge_outcome challenge_state CHECK (challenge_outcome <> 'ongoing');
+
+UPDATE reports
+SET challenge_outcome = status_challenges.state
+FROM status_challenges
+WHERE status_challenges.report_id = reports.id;
+
+CREATE OR REPLACE FUNCTION finish_status_challenge(
+    challenge_id INT,
+    outcome challenge_state
+) RETURNS VOID AS $$
+DECLARE
+    challenge status_challenges;
+    new_report_id INT;
+BEGIN
+    SELECT * INTO challenge
+    FROM status_challenges
+    WHERE id = challenge_id AND state = 'ongoing'
+    FOR UPDATE;
+
+    IF NOT FOUND THEN
+        RETURN;
+    END IF;
+
+    INSERT INTO reports (
+        from_program_id,
+        from_status_level,
+        to_program_id,
+        to_status_level,
+        result,
+        challenge_outcome,
+        notes,
+        state,
+        user_id
+    ) VALUES (
+        challenge.from_program_id,
+        challenge.from_status_level,
+        challenge.to_program_id,
+        challenge.to_status_level,
+        'challenge',
+        outcome,
+        CASE outcome
+            WHEN 'completed' THEN 'Completed the status challenge.'
+            ELSE 'Failed the status challenge by the deadline.'
+        END,
+        'pending',
+        challenge.user_id
+    )
+    RETURNING id INTO new_report_id;
+
+    UPDATE status_challenges
+    SET
+        state = outcome,
+        finished_at = NOW(),
+        report_id = new_report_id
+    WHERE id = challenge_id;
+END;
+$$ LANGUAGE plpgsql;
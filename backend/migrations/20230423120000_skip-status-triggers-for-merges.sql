@@ -0,0 +1,51 @@
+-- Merging a status moves users to another level without their status having
+-- changed, so a merge sets `statusmatch.merging_status` for its transaction
+-- and the triggers of user_statuses leave the move alone.
+CREATE OR REPLACE FUNCTION record_user_status_change() RETURNS TRIGGER AS $$
+DECLARE
+    previous_level INT;
+BEGIN
+    IF current_setting('statusmatch.merging_status', true) = 'on' THEN
+        RETURN NEW;
+    END IF;
+
+    IF TG_OP = 'UPDATE' THEN
+        IF OLD.level = NEW.level THEN
+            RETURN NEW;
+        END IF;
+        previous_level := OLD.level;
+    END IF;
+
+    INSERT INTO user_status_history (user_id, program_id, previous_level, level, source)
+    VALUES (NEW.user_id, NEW.program_id, previous_level, NEW.level, NEW.source);
+
+    PERFORM pg_notify('status_changed', json_build_object(
+        'user_id', NEW.user_id,
+        'program_id', NEW.program_id,
+        'previous_level', previous_level,
+        'level', NEW.level,
+        'source', NEW.source
+    )::text);
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION complete_status_challenges() RETURNS TRIGGER AS $$
+BEGIN
+    IF current_setting('statusmatch.merging_status', true) = 'on' THEN
+        RETURN NEW;
+    END IF;
+
+    PERFORM finish_status_challenge(id, 'completed')
+    FROM status_challenges
+    WHERE
+        user_id = NEW.user_id
+        AND to_program_id = NEW.program_id
+        AND to_status_level <= NEW.level
+        AND state = 'ongoing'
+        AND deadline >= CURRENT_DATE;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,30 @@
+-- Approved reports weighted by their age for tallies: a report counts half as
+-- much every `half_life_days`, and undated reports count `undated_weight`.
+-- With `months`, older reports are left out.
+CREATE FUNCTION weighted_reports(half_life_days FLOAT8, undated_weight FLOAT8, months INT)
+RETURNS TABLE (
+    from_program_id INT,
+    from_status_level INT,
+    to_program_id INT,
+    to_status_level INT,
+    result report_result,
+    weight FLOAT8
+) AS $$
+    SELECT
+        reports.from_program_id,
+        reports.from_status_level,
+        reports.to_program_id,
+        reports.to_status_level,
+        reports.result,
+        COALESCE(
+            POWER(
+                0.5,
+                EXTRACT(EPOCH FROM NOW() - reports.created_at)::FLOAT8 / (86400 * half_life_days)
+            ),
+            undated_weight
+        )
+    FROM reports
+    WHERE
+        reports.state = 'approved'
+        AND (months IS NULL OR reports.created_at >= NOW() - make_interval(months => months))
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,632 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use scraper::error::ScrapeFailure;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    auth::{AdminClaims, Claims},
+    ensure_program,
+    error::ApiError,
+    reports::{self, ReportState},
+    Program,
+};
+
+/// Entries of the audit log and scrape failures returned at once.
+const PAGE_SIZE: i64 = 100;
+
+#[derive(Debug)]
+pub enum AdminError {
+    StatusNotFound,
+    ReportNotFound,
+    NameTaken,
+    InvalidState,
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            AdminError::StatusNotFound => (StatusCode::NOT_FOUND, "Status is not found"),
+            AdminError::ReportNotFound => (StatusCode::NOT_FOUND, "Report is not found"),
+            AdminError::NameTaken => (StatusCode::CONFLICT, "Another program has the name"),
+            AdminError::InvalidState => (
+                StatusCode::BAD_REQUEST,
+                "A report can only be approved or rejected",
+            ),
+        };
+
+        let body = Json(json!({
+            "error": error_message,
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+/// Records a change in the same transaction as the change itself.
+async fn audit(
+    conn: &mut PgConnection,
+    user_id: i32,
+    action: &str,
+    details: serde_json::Value,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "INSERT INTO audit_log (user_id, action, details) VALUES ($1, $2, $3)",
+        user_id,
+        action,
+        details,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<&str, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 255 {
+        return Err(ApiError::InvalidInput(
+            "name must be between 1 and 255 characters",
+        ));
+    }
+    Ok(name)
+}
+
+#[derive(Deserialize)]
+pub struct Rename {
+    name: String,
+}
+
+pub async fn rename_program(
+    AdminClaims(Claims { user_id, .. }): AdminClaims,
+    State(pool): State<PgPool>,
+    Path(id): Path<i32>,
+    Json(Rename { name }): Json<Rename>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = validate_name(&name)?;
+
+    let mut trans = pool.begin().await?;
+
+    let previous = sqlx::query_scalar!("SELECT name FROM programs WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut trans)
+        .await?
+        .ok_or(ApiError::ProgramNotFound)?;
+
+    let taken = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM programs WHERE name = $1 AND id <> $2) AS "taken!""#,
+        name,
+        id,
+    )
+    .fetch_one(&mut trans)
+    .await?;
+    if taken {
+        return Err(AdminError::NameTaken.into());
+    }
+
+    sqlx::query!("UPDATE programs SET name = $2 WHERE id = $1", id, name)
+        .execute(&mut trans)
+        .await?;
+
+    let details = json!({ "program_id": id, "from": previous, "to": name });
+    audit(&mut trans, user_id, "rename_program", details).await?;
+
+    trans.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Adds a level to the ladder of the program or renames an existing one.
+pub async fn put_status(
+    AdminClaims(Claims { user_id, .. }): AdminClaims,
+    State(pool): State<PgPool>,
+    Path((id, level)): Path<(i32, i32)>,
+    Json(Rename { name }): Json<Rename>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = validate_name(&name)?;
+
+    let mut trans = pool.begin().await?;
+    ensure_program(&mut trans, id).await?;
+
+    let previous = sqlx::query_scalar!(
+        "SELECT name FROM program_statuses WHERE program_id = $1 AND level = $2 FOR UPDATE",
+        id,
+        level,
+    )
+    .fetch_optional(&mut trans)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO program_statuses (program_id, level, name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (program_id, level)
+        DO UPDATE SET name = $3
+        "#,
+        id,
+        level,
+        name,
+    )
+    .execute(&mut trans)
+    .await?;
+
+    let details = json!({ "program_id": id, "level": level, "from": previous, "to": name });
+    audit(&mut trans, user_id, "put_status", details).await?;
+
+    trans.commit().await?;
+
+    Ok(if previous.is_some() {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    })
+}
+
+#[derive(Deserialize)]
+pub struct Merge {
+    /// The level that takes over everything of the merged one.
+    into: i32,
+}
+
+/// Folds a duplicate status into another level of the same program: reports,
+/// user statuses, their history, status challenges and the source ids of the
+/// sync move over and the duplicate is removed.
+pub async fn merge_status(
+    AdminClaims(Claims { user_id, .. }): AdminClaims,
+    State(pool): State<PgPool>,
+    Path((id, level)): Path<(i32, i32)>,
+    Json(Merge { into }): Json<Merge>,
+) -> Result<impl IntoResponse, ApiError> {
+    if level == into {
+        return Err(ApiError::InvalidInput(
+            "A status cannot be merged into itself",
+        ));
+    }
+
+    let mut trans = pool.begin().await?;
+    ensure_program(&mut trans, id).await?;
+
+    let statuses = sqlx::query!(
+        r#"
+        SELECT level, name
+        FROM program_statuses
+        WHERE program_id = $1 AND level IN ($2, $3)
+        FOR UPDATE
+        "#,
+        id,
+        level,
+        into,
+    )
+    .fetch_all(&mut trans)
+    .await?;
+    if statuses.len() != 2 {
+        return Err(AdminError::StatusNotFound.into());
+    }
+
+    let reports = sqlx::query!(
+        r#"
+        UPDATE reports
+        SET
+            from_status_level = CASE
+                WHEN from_program_id = $1 AND from_status_level = $2 THEN $3
+                ELSE from_status_level
+            END,
+            to_status_level = CASE
+                WHEN to_program_id = $1 AND to_status_level = $2 THEN $3
+                ELSE to_status_level
+            END
+        WHERE
+            (from_program_id = $1 AND from_status_level = $2)
+            OR (to_program_id = $1 AND to_status_level = $2)
+        "#,
+        id,
+        level,
+        into,
+    )
+    .execute(&mut trans)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+        UPDATE user_status_history
+        SET
+            level = CASE WHEN level = $2 THEN $3 ELSE level END,
+            previous_level = CASE WHEN previous_level = $2 THEN $3 ELSE previous_level END
+        WHERE
+            program_id = $1
+            AND (level = $2 OR previous_level = $2)
+        "#,
+        id,
+        level,
+        into,
+    )
+    .execute(&mut trans)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE status_challenges
+        SET
+            from_status_level = CASE
+                WHEN from_program_id = $1 AND from_status_level = $2 THEN $3
+                ELSE from_status_level
+            END,
+            to_status_level = CASE
+                WHEN to_program_id = $1 AND to_status_level = $2 THEN $3
+                ELSE to_status_level
+            END
+        WHERE
+            (from_program_id = $1 AND from_status_level = $2)
+            OR (to_program_id = $1 AND to_status_level = $2)
+        "#,
+        id,
+        level,
+        into,
+    )
+    .execute(&mut trans)
+    .await?;
+
+    // The sync keeps mapping the merged status of statusmatcher.com here.
+    sqlx::query!(
+        "UPDATE program_status_sources SET level = $3 WHERE program_id = $1 AND level = $2",
+        id,
+        level,
+        into,
+    )
+    .execute(&mut trans)
+    .await?;
+
+    // Users keep the status they had, so the move is neither recorded in their
+    // history, nor notified, nor taken as reaching the level of a challenge.
+    sqlx::query!("SELECT set_config('statusmatch.merging_status', 'on', true)")
+        .fetch_one(&mut trans)
+        .await?;
+
+    let users = sqlx::query!(
+        "UPDATE user_statuses SET level = $3 WHERE program_id = $1 AND level = $2",
+        id,
+        level,
+        into,
+    )
+    .execute(&mut trans)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        "DELETE FROM program_statuses WHERE program_id = $1 AND level = $2",
+        id,
+        level,
+    )
+    .execute(&mut trans)
+    .await?;
+
+    let merged = statuses.iter().find(|status| status.level == level);
+    let details = json!({
+        "program_id": id,
+        "level": level,
+        "name": merged.map(|status| &status.name),
+        "into": into,
+        "reports": reports,
+        "users": users,
+    });
+    audit(&mut trans, user_id, "merge_status", details).await?;
+
+    trans.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    /// Pending unless given.
+    state: Option<ReportState>,
+}
+
+pub async fn list_reports(
+    AdminClaims(_): AdminClaims,
+    State(pool): State<PgPool>,
+    Query(ReportQuery { state }): Query<ReportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let state = state.unwrap_or(ReportState::Pending);
+    let reports = reports::find_reports(&mut conn, None, Some(state)).await?;
+
+    Ok((StatusCode::OK, Json(reports)))
+}
+
+#[derive(Deserialize)]
+pub struct Moderation {
+    state: ReportState,
+}
+
+pub async fn moderate_report(
+    AdminClaims(Claims { user_id, .. }): AdminClaims,
+    State(pool): State<PgPool>,
+    Path(id): Path<i32>,
+    Json(Moderation { state }): Json<Moderation>,
+) -> Result<impl IntoResponse, ApiError> {
+    if matches!(state, ReportState::Pending) {
+        return Err(AdminError::InvalidState.into());
+    }
+
+    let mut trans = pool.begin().await?;
+
+    let previous = sqlx::query_scalar!(
+        r#"SELECT state AS "state: ReportState" FROM reports WHERE id = $1 FOR UPDATE"#,
+        id,
+    )
+    .fetch_optional(&mut trans)
+    .await?
+    .ok_or(AdminError::ReportNotFound)?;
+
+    sqlx::query!(
+        "UPDATE reports SET state = $2 WHERE id = $1",
+        id,
+        state as ReportState,
+    )
+    .execute(&mut trans)
+    .await?;
+
+    let details = json!({ "report_id": id, "from": previous, "to": state });
+    audit(&mut trans, user_id, "moderate_report", details).await?;
+
+    trans.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+struct ScrapeFailureEntry {
+    id: i32,
+    /// Absent for results of the daemon.
+    run_id: Option<i32>,
+    user_id: i32,
+    program: Program,
+    failure: ScrapeFailure,
+    message: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// The latest failures of the scraper, to spot programs whose pages changed.
+pub async fn list_scrape_failures(
+    AdminClaims(_): AdminClaims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let failures = sqlx::query_as!(
+        ScrapeFailureEntry,
+        r#"
+        SELECT
+            scrape_results.id,
+            scrape_results.run_id,
+            scrape_results.user_id,
+            (programs.id, programs.name) AS "program!: Program",
+            scrape_results.failure AS "failure!: ScrapeFailure",
+            scrape_results.message,
+            scrape_results.created_at
+        FROM scrape_results
+        INNER JOIN programs
+            ON scrape_results.program_id = programs.id
+        WHERE scrape_results.failure IS NOT NULL
+        ORDER BY scrape_results.created_at DESC
+        LIMIT $1
+        "#,
+        PAGE_SIZE,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    Ok((StatusCode::OK, Json(failures)))
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    id: i32,
+    /// Absent once the admin has deleted their account.
+    user_id: Option<i32>,
+    action: String,
+    details: serde_json::Value,
+    created_at: DateTime<Utc>,
+}
+
+pub async fn list_audit_log(
+    AdminClaims(_): AdminClaims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT id, user_id, action, details, created_at
+        FROM audit_log
+        ORDER BY created_at DESC
+        LIMIT $1
+        "#,
+        PAGE_SIZE,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    Ok((StatusCode::OK, Json(entries)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::FromRequestParts,
+        http::{header::AUTHORIZATION, Request},
+    };
+
+    use super::*;
+    use crate::{
+        auth::Role,
+        testing::{add_program, add_user, claims, keys, login, status_of, AuthState},
+    };
+
+    async fn admin(pool: &PgPool) -> AdminClaims {
+        let user_id = add_user(pool, Role::Admin).await;
+        AdminClaims(claims(user_id, Role::Admin))
+    }
+
+    async fn extract(pool: &PgPool, role: Role) -> Result<AdminClaims, ApiError> {
+        let user_id = add_user(pool, role).await;
+        let (token, _) = login(pool, user_id).await;
+
+        let (mut parts, _) = Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        let state = AuthState {
+            pool: pool.clone(),
+            keys: keys(),
+        };
+        AdminClaims::from_request_parts(&mut parts, &state).await
+    }
+
+    #[sqlx::test]
+    async fn should_forbid_members(pool: PgPool) {
+        assert!(extract(&pool, Role::Admin).await.is_ok());
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            status_of(extract(&pool, Role::Member).await.map(|_| StatusCode::OK))
+        );
+    }
+
+    #[sqlx::test]
+    async fn should_not_rename_program_to_taken_name(pool: PgPool) {
+        let hilton = add_program(&pool, "Hilton Honors", &[]).await;
+        add_program(&pool, "Marriott Bonvoy", &[]).await;
+
+        let rename = |name: &str| {
+            Json(Rename {
+                name: name.to_string(),
+            })
+        };
+        let status = status_of(
+            rename_program(
+                admin(&pool).await,
+                State(pool.clone()),
+                Path(hilton),
+                rename(" Marriott Bonvoy "),
+            )
+            .await,
+        );
+        assert_eq!(StatusCode::CONFLICT, status);
+
+        let status = status_of(
+            rename_program(
+                admin(&pool).await,
+                State(pool.clone()),
+                Path(hilton),
+                rename("Hilton"),
+            )
+            .await,
+        );
+        assert_eq!(StatusCode::NO_CONTENT, status);
+    }
+
+    #[sqlx::test]
+    async fn should_merge_status(pool: PgPool) {
+        let program = add_program(&pool, "Hilton Honors", &["Gold", "Diamond", "Diamond"]).await;
+        let other = add_program(&pool, "Marriott Bonvoy", &["Gold Elite"]).await;
+        let user_id = add_user(&pool, Role::Member).await;
+        sqlx::query("INSERT INTO user_statuses (user_id, program_id, level) VALUES ($1, $2, 3)")
+            .bind(user_id)
+            .bind(program)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO reports (from_program_id, from_status_level, to_program_id, to_status_level, result)
+            VALUES ($1, 3, $2, 1, 'match')
+            "#,
+        )
+        .bind(program)
+        .bind(other)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let status = status_of(
+            merge_status(
+                admin(&pool).await,
+                State(pool.clone()),
+                Path((program, 3)),
+                Json(Merge { into: 2 }),
+            )
+            .await,
+        );
+        assert_eq!(StatusCode::NO_CONTENT, status);
+
+        let levels: Vec<i32> = sqlx::query_scalar(
+            "SELECT level FROM program_statuses WHERE program_id = $1 ORDER BY level",
+        )
+        .bind(program)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(vec![1, 2], levels);
+
+        let level: i32 = sqlx::query_scalar("SELECT level FROM user_statuses WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(2, level);
+
+        let from_level: i32 = sqlx::query_scalar("SELECT from_status_level FROM reports")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(2, from_level);
+
+        // The move is not a change of level.
+        let history: Vec<(Option<i32>, i32)> = sqlx::query_as(
+            "SELECT previous_level, level FROM user_status_history WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(vec![(None, 2)], history);
+
+        let audited: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM audit_log WHERE action = 'merge_status'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(1, audited);
+    }
+
+    #[sqlx::test]
+    async fn should_not_merge_unknown_status(pool: PgPool) {
+        let program = add_program(&pool, "Hilton Honors", &["Gold", "Diamond"]).await;
+
+        let merge = |level, into| {
+            let pool = pool.clone();
+            async move {
+                status_of(
+                    merge_status(
+                        admin(&pool).await,
+                        State(pool.clone()),
+                        Path((program, level)),
+                        Json(Merge { into }),
+                    )
+                    .await,
+                )
+            }
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, merge(2, 2).await);
+        assert_eq!(StatusCode::NOT_FOUND, merge(3, 2).await);
+    }
+}
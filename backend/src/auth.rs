@@ -1,58 +1,77 @@
 use axum::{
     async_trait,
-    extract::{FromRequestParts, TypedHeader},
+    extract::{FromRef, FromRequestParts, TypedHeader},
     headers::{authorization::Bearer, Authorization},
     http::{request::Parts, StatusCode},
     response::{IntoResponse, Response},
     Json, RequestPartsExt,
 };
-use jsonwebtoken::{decode, DecodingKey, EncodingKey, Validation};
-use once_cell::sync::Lazy;
+use jsonwebtoken::{decode, decode_header, Header, Validation};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+
+use crate::{error::ApiError, keys::Keys};
+
+/// Access tokens are short-lived; clients keep sessions alive with refresh tokens.
+const ACCESS_TOKEN_TTL: chrono::Duration = chrono::Duration::minutes(15);
+
+/// A session ends when it has not been refreshed for this long.
+pub const SESSION_TTL: chrono::Duration = chrono::Duration::days(30);
 
 #[derive(Debug)]
 pub enum AuthError {
     WaitingForLogin,
     InvalidToken,
+    InvalidRefreshToken,
+    SessionNotFound,
+    InvalidChallenge,
+    ChallengeExpired,
+    ChallengeConsumed,
+    Forbidden,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Member,
+    Admin,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
+    /// The id of the user.
     pub sub: String,
     pub exp: i64,
+    /// Identifies the session, which must not have been revoked.
+    pub jti: String,
+    /// As of when the token was issued, so a new role applies from the next refresh.
+    #[serde(default)]
+    pub role: Role,
+    /// `sub` as parsed by the extractor.
+    #[serde(skip)]
+    pub user_id: i32,
 }
 
-pub struct Keys {
-    encoding: EncodingKey,
-    decoding: DecodingKey,
-}
-
-impl Keys {
-    fn new(secret: &[u8]) -> Self {
-        Self {
-            encoding: EncodingKey::from_secret(secret),
-            decoding: DecodingKey::from_secret(secret),
-        }
-    }
-}
-
-static KEYS: Lazy<Keys> = Lazy::new(|| {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    Keys::new(secret.as_bytes())
-});
-
 #[derive(Debug, Serialize)]
 pub struct Auth {
     access_token: String,
     token_type: String,
+    expires_in: i64,
+    refresh_token: String,
 }
 
 impl Auth {
-    fn new(access_token: String) -> Self {
+    fn new(access_token: String, refresh_token: String) -> Self {
         Self {
             access_token,
             token_type: "Bearer".to_string(),
+            expires_in: ACCESS_TOKEN_TTL.num_seconds(),
+            refresh_token,
         }
     }
 }
@@ -63,14 +82,143 @@ impl IntoResponse for Auth {
     }
 }
 
-pub fn authorize(pubkey: Vec<u8>) -> jsonwebtoken::errors::Result<Auth> {
+fn random_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// Refresh tokens are stored hashed so that a database leak does not leak sessions.
+pub fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+fn access_token(
+    keys: &Keys,
+    user_id: i32,
+    role: Role,
+    jti: String,
+) -> jsonwebtoken::errors::Result<String> {
     let claims = Claims {
-        sub: hex::encode(pubkey),
-        exp: (chrono::Utc::now() + chrono::Duration::days(1)).timestamp(),
+        sub: user_id.to_string(),
+        exp: (chrono::Utc::now() + ACCESS_TOKEN_TTL).timestamp(),
+        jti,
+        role,
+        user_id,
     };
 
-    let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &KEYS.encoding)?;
-    Ok(Auth::new(token))
+    let key = keys.current();
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    jsonwebtoken::encode(&header, &claims, &key.encoding)
+}
+
+/// Starts a session and issues its first pair of tokens.
+pub async fn authorize(
+    conn: &mut PgConnection,
+    keys: &Keys,
+    user_id: i32,
+    user_agent: Option<String>,
+) -> Result<Auth, ApiError> {
+    let jti = random_token();
+    let refresh_token = random_token();
+
+    let role = sqlx::query_scalar!(
+        r#"
+        INSERT INTO sessions (
+            user_id,
+            refresh_token_hash,
+            access_token_id,
+            user_agent,
+            expires_at
+        ) VALUES ($1, $2, $3, $4, NOW() + make_interval(secs => $5))
+        RETURNING (SELECT role FROM users WHERE id = $1) AS "role!: Role"
+        "#,
+        user_id,
+        hash_token(&refresh_token),
+        &jti,
+        user_agent,
+        SESSION_TTL.num_seconds() as f64,
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(Auth::new(
+        access_token(keys, user_id, role, jti)?,
+        refresh_token,
+    ))
+}
+
+/// Replaces both tokens of the session that `refresh_token` belongs to.
+///
+/// The old refresh token and access token stop working at once. Presenting a
+/// replaced refresh token revokes the session, since either its holder or
+/// whoever stole it is no longer the one refreshing it.
+pub async fn refresh(
+    conn: &mut PgConnection,
+    keys: &Keys,
+    refresh_token: &str,
+) -> Result<Auth, ApiError> {
+    let jti = random_token();
+    let new_refresh_token = random_token();
+
+    let session = sqlx::query!(
+        r#"
+        WITH refreshed AS (
+            UPDATE sessions
+            SET
+                refresh_token_hash = $2,
+                access_token_id = $3,
+                refreshed_at = NOW(),
+                expires_at = NOW() + make_interval(secs => $4)
+            WHERE
+                refresh_token_hash = $1
+                AND revoked_at IS NULL
+                AND expires_at > NOW()
+            RETURNING
+                id,
+                user_id,
+                (SELECT role FROM users WHERE id = sessions.user_id) AS role
+        ),
+        rotated AS (
+            INSERT INTO rotated_refresh_tokens (refresh_token_hash, session_id)
+            SELECT $1, id FROM refreshed
+        )
+        SELECT user_id, role AS "role!: Role" FROM refreshed
+        "#,
+        hash_token(refresh_token),
+        hash_token(&new_refresh_token),
+        &jti,
+        SESSION_TTL.num_seconds() as f64,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(session) = session else {
+        let reused = sqlx::query_scalar!(
+            r#"
+            UPDATE sessions
+            SET revoked_at = NOW()
+            FROM rotated_refresh_tokens
+            WHERE
+                rotated_refresh_tokens.refresh_token_hash = $1
+                AND sessions.id = rotated_refresh_tokens.session_id
+                AND sessions.revoked_at IS NULL
+            RETURNING sessions.id
+            "#,
+            hash_token(refresh_token),
+        )
+        .fetch_optional(conn)
+        .await?;
+        if let Some(id) = reused {
+            tracing::warn!("Revoked session {} whose refresh token was reused", id);
+        }
+        return Err(AuthError::InvalidRefreshToken.into());
+    };
+
+    Ok(Auth::new(
+        access_token(keys, session.user_id, session.role, jti)?,
+        new_refresh_token,
+    ))
 }
 
 impl IntoResponse for AuthError {
@@ -78,6 +226,12 @@ impl IntoResponse for AuthError {
         let (status, error_message) = match self {
             AuthError::WaitingForLogin => (StatusCode::UNAUTHORIZED, "Waiting for login"),
             AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
+            AuthError::InvalidRefreshToken => (StatusCode::UNAUTHORIZED, "Invalid refresh token"),
+            AuthError::SessionNotFound => (StatusCode::NOT_FOUND, "Session is not found"),
+            AuthError::InvalidChallenge => (StatusCode::BAD_REQUEST, "Invalid challenge"),
+            AuthError::ChallengeExpired => (StatusCode::GONE, "Challenge has expired"),
+            AuthError::ChallengeConsumed => (StatusCode::GONE, "Token has already been issued"),
+            AuthError::Forbidden => (StatusCode::FORBIDDEN, "Admin role is required"),
         };
 
         let body = Json(json!({
@@ -91,19 +245,80 @@ impl IntoResponse for AuthError {
 #[async_trait]
 impl<S> FromRequestParts<S> for Claims
 where
+    PgPool: FromRef<S>,
+    Arc<Keys>: FromRef<S>,
     S: Send + Sync,
 {
-    type Rejection = AuthError;
+    type Rejection = ApiError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| AuthError::InvalidToken)?;
 
-        let token_data = decode::<Claims>(bearer.token(), &KEYS.decoding, &Validation::default())
+        let keys = Arc::<Keys>::from_ref(state);
+        let key = decode_header(bearer.token())
+            .ok()
+            .and_then(|header| keys.get(&header.kid?))
+            .ok_or(AuthError::InvalidToken)?;
+        let mut token_data = decode::<Claims>(
+            bearer.token(),
+            &key.decoding,
+            &Validation::new(key.algorithm),
+        )
+        .map_err(|_| AuthError::InvalidToken)?;
+
+        // Tokens issued before accounts had ids carry a pubkey.
+        token_data.claims.user_id = token_data
+            .claims
+            .sub
+            .parse()
             .map_err(|_| AuthError::InvalidToken)?;
 
+        // A signed token is not enough once its session has been revoked or refreshed.
+        let pool = PgPool::from_ref(state);
+        let active = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM sessions
+                WHERE
+                    access_token_id = $1
+                    AND revoked_at IS NULL
+                    AND expires_at > NOW()
+            ) AS "active!"
+            "#,
+            &token_data.claims.jti,
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        if !active {
+            return Err(AuthError::InvalidToken.into());
+        }
         Ok(token_data.claims)
     }
 }
+
+/// The claims of an admin, rejecting everyone else with 403.
+pub struct AdminClaims(pub Claims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminClaims
+where
+    PgPool: FromRef<S>,
+    Arc<Keys>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+
+        if claims.role != Role::Admin {
+            return Err(AuthError::Forbidden.into());
+        }
+        Ok(AdminClaims(claims))
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use once_cell::sync::Lazy;
+use scraper::{
+    error::{ScrapeError, ScrapeFailure},
+    registry::Registry,
+    vault::{Keyring, Sealed},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{auth::Claims, error::ApiError, Program};
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::default);
+
+/// Every login test runs its own headless Chrome.
+const MAX_LOGIN_TESTS: usize = 2;
+
+#[derive(Debug)]
+pub enum CredentialError {
+    InvalidInput,
+    ProgramNotFound,
+    ScraperNotRegistered,
+    AlreadyLinked,
+    NotLinked,
+    TestInProgress,
+    TooManyTests,
+    LoginFailed(ScrapeError),
+}
+
+impl IntoResponse for CredentialError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match &self {
+            CredentialError::InvalidInput => (
+                StatusCode::BAD_REQUEST,
+                "Username and password are required",
+            ),
+            CredentialError::ProgramNotFound => (StatusCode::NOT_FOUND, "Program is not found"),
+            CredentialError::ScraperNotRegistered => {
+                (StatusCode::BAD_REQUEST, "Program has no registered scraper")
+            }
+            CredentialError::AlreadyLinked => (StatusCode::CONFLICT, "Program is already linked"),
+            CredentialError::NotLinked => (StatusCode::NOT_FOUND, "Program is not linked"),
+            CredentialError::TestInProgress => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Another login test is in progress",
+            ),
+            CredentialError::TooManyTests => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many login tests are in progress, try again later",
+            ),
+            CredentialError::LoginFailed(_) => (StatusCode::UNPROCESSABLE_ENTITY, "Login failed"),
+        };
+
+        let body = match self {
+            CredentialError::LoginFailed(err) => Json(json!({
+                "error": error_message,
+                "failure": err.failure,
+                "message": err.message,
+            })),
+            _ => Json(json!({
+                "error": error_message,
+            })),
+        };
+
+        (status, body).into_response()
+    }
+}
+
+/// Limits login tests to `MAX_LOGIN_TESTS` at once, and to one at a time per user.
+#[derive(Clone)]
+pub struct LoginTests {
+    permits: Arc<Semaphore>,
+    users: Arc<Mutex<HashSet<i32>>>,
+}
+
+impl Default for LoginTests {
+    fn default() -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(MAX_LOGIN_TESTS)),
+            users: Arc::default(),
+        }
+    }
+}
+
+/// Lets the next test of the user start once dropped.
+struct LoginTest {
+    _permit: OwnedSemaphorePermit,
+    user_id: i32,
+    users: Arc<Mutex<HashSet<i32>>>,
+}
+
+impl LoginTests {
+    fn start(&self, user_id: i32) -> Result<LoginTest, CredentialError> {
+        let mut users = self.users.lock().unwrap();
+        if users.contains(&user_id) {
+            return Err(CredentialError::TestInProgress);
+        }
+        let permit = self
+            .permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| CredentialError::TooManyTests)?;
+        users.insert(user_id);
+
+        Ok(LoginTest {
+            _permit: permit,
+            user_id,
+            users: self.users.clone(),
+        })
+    }
+}
+
+impl Drop for LoginTest {
+    fn drop(&mut self) {
+        self.users.lock().unwrap().remove(&self.user_id);
+    }
+}
+
+#[derive(Serialize)]
+struct LinkedProgram {
+    program: Program,
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewCredential {
+    program_id: i32,
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct UpdatedCredential {
+    username: String,
+    password: String,
+}
+
+fn validate(username: &str, password: &str) -> Result<(), CredentialError> {
+    if username.trim().is_empty() || username.len() > 255 || password.is_empty() {
+        return Err(CredentialError::InvalidInput);
+    }
+    Ok(())
+}
+
+fn seal(keyring: &Keyring, password: &str) -> Result<Sealed, ApiError> {
+    keyring.encrypt(password).map_err(|err| {
+        tracing::error!("Failed to encrypt a password: {}", err);
+        ApiError::Internal
+    })
+}
+
+async fn ensure_scraper(conn: &mut PgConnection, program_id: i32) -> Result<(), ApiError> {
+    let slug = sqlx::query_scalar!("SELECT slug FROM programs WHERE id = $1", program_id)
+        .fetch_optional(conn)
+        .await?
+        .ok_or(CredentialError::ProgramNotFound)?;
+
+    match slug {
+        Some(slug) if REGISTRY.get(&slug).is_some() => Ok(()),
+        _ => Err(CredentialError::ScraperNotRegistered.into()),
+    }
+}
+
+pub async fn list_credentials(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let linked_programs = sqlx::query_as!(
+        LinkedProgram,
+        r#"
+        SELECT
+            (
+                programs.id,
+                programs.name
+            ) AS "program!: Program",
+            user_credentials.username
+        FROM user_credentials
+        INNER JOIN programs
+            ON user_credentials.program_id = programs.id
+        WHERE
+            user_credentials.user_id = $1
+        ORDER BY
+            programs.name
+        "#,
+        user_id,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    Ok((StatusCode::OK, Json(linked_programs)))
+}
+
+pub async fn add_credential(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    State(keyring): State<Arc<Keyring>>,
+    Json(NewCredential {
+        program_id,
+        username,
+        password,
+    }): Json<NewCredential>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate(&username, &password)?;
+
+    let mut conn = pool.acquire().await?;
+    ensure_scraper(&mut conn, program_id).await?;
+
+    let sealed = seal(&keyring, &password)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_credentials (
+            user_id,
+            program_id,
+            username,
+            password_ciphertext,
+            data_key,
+            key_version
+        ) VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT DO NOTHING
+        RETURNING program_id
+        "#,
+        user_id,
+        program_id,
+        username.trim(),
+        &sealed.ciphertext,
+        &sealed.data_key,
+        sealed.key_version,
+    )
+    .fetch_optional(&mut conn)
+    .await?
+    .ok_or(CredentialError::AlreadyLinked)?;
+
+    Ok(StatusCode::CREATED)
+}
+
+pub async fn update_credential(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    State(keyring): State<Arc<Keyring>>,
+    Path(program_id): Path<i32>,
+    Json(UpdatedCredential { username, password }): Json<UpdatedCredential>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate(&username, &password)?;
+
+    let mut conn = pool.acquire().await?;
+    ensure_scraper(&mut conn, program_id).await?;
+
+    let sealed = seal(&keyring, &password)?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_credentials
+        SET
+            username = $3,
+            password = NULL,
+            password_ciphertext = $4,
+            data_key = $5,
+            key_version = $6
+        WHERE
+            user_id = $1
+            AND program_id = $2
+        "#,
+        user_id,
+        program_id,
+        username.trim(),
+        &sealed.ciphertext,
+        &sealed.data_key,
+        sealed.key_version,
+    )
+    .execute(&mut conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(CredentialError::NotLinked.into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_credential(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Path(program_id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM user_credentials WHERE user_id = $1 AND program_id = $2",
+        user_id,
+        program_id,
+    )
+    .execute(&mut conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(CredentialError::NotLinked.into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Logs in to the program with the stored credential without updating the status.
+pub async fn test_credential(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    State(keyring): State<Arc<Keyring>>,
+    State(login_tests): State<LoginTests>,
+    Path(program_id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    let test = login_tests.start(user_id)?;
+
+    let mut conn = pool.acquire().await?;
+    ensure_scraper(&mut conn, program_id).await?;
+
+    let credential = sqlx::query!(
+        r#"
+        SELECT
+            programs.slug AS "slug!",
+            user_credentials.username,
+            user_credentials.password_ciphertext,
+            user_credentials.data_key,
+            user_credentials.key_version
+        FROM user_credentials
+        INNER JOIN programs
+            ON user_credentials.program_id = programs.id
+        WHERE
+            user_credentials.user_id = $1
+            AND user_credentials.program_id = $2
+        "#,
+        user_id,
+        program_id,
+    )
+    .fetch_optional(&mut conn)
+    .await?
+    .ok_or(CredentialError::NotLinked)?;
+
+    let sealed = scraper::sealed_password(
+        &credential.password_ciphertext,
+        &credential.data_key,
+        credential.key_version,
+    )
+    .ok_or_else(|| {
+        CredentialError::LoginFailed(ScrapeError::new(
+            ScrapeFailure::Other,
+            "The password is not encrypted yet.",
+        ))
+    })?;
+    let password = keyring
+        .decrypt(&sealed)
+        .map_err(|err| CredentialError::LoginFailed(err.into()))?;
+    let program_scraper = REGISTRY
+        .get(&credential.slug)
+        .ok_or(CredentialError::ScraperNotRegistered)?;
+
+    // Nothing is written, so the connection goes back to the pool for the scrape.
+    drop(conn);
+
+    // The test counts as running until Chrome exits, even past the timeout.
+    let status =
+        scraper::retrieve_status_holding(program_scraper, credential.username, password, test)
+            .await
+            .map_err(CredentialError::LoginFailed)?;
+
+    Ok((StatusCode::OK, Json(json!({ "status": status }))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_run_one_login_test_at_a_time_per_user() {
+        let tests = LoginTests::default();
+
+        let test = tests.start(1).unwrap();
+        assert!(matches!(
+            tests.start(1),
+            Err(CredentialError::TestInProgress)
+        ));
+
+        drop(test);
+        assert!(tests.start(1).is_ok());
+    }
+
+    #[test]
+    fn should_limit_login_tests_at_once() {
+        let tests = LoginTests::default();
+
+        let running: Vec<_> = (0..MAX_LOGIN_TESTS as i32)
+            .map(|user_id| tests.start(user_id).unwrap())
+            .collect();
+        assert!(matches!(
+            tests.start(MAX_LOGIN_TESTS as i32),
+            Err(CredentialError::TooManyTests)
+        ));
+
+        drop(running);
+        assert!(tests.start(MAX_LOGIN_TESTS as i32).is_ok());
+    }
+}
@@ -0,0 +1,166 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use crate::{
+    admin::AdminError, auth::AuthError, credentials::CredentialError, lnurl::LnurlError,
+    nostr::NostrError, reports::ReportError, status_challenges::ChallengeError,
+    statuses::StatusError, user_keys::KeyError,
+};
+
+/// The error of every handler.
+///
+/// Failures of the request render as `{"error": ...}` with a 4xx status,
+/// failures of the server are traced and hidden behind a 5xx status.
+#[derive(Debug)]
+pub enum ApiError {
+    InvalidInput(&'static str),
+    ProgramNotFound,
+    DatabaseUnavailable,
+    Internal,
+    Admin(AdminError),
+    Auth(AuthError),
+    Challenge(ChallengeError),
+    Credential(CredentialError),
+    Key(KeyError),
+    /// Rendered for wallets as LUD-04.
+    Lnurl(LnurlError),
+    Nostr(NostrError),
+    Report(ReportError),
+    Status(StatusError),
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        tracing::error!("Database error: {}", err);
+        match err {
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_) => ApiError::DatabaseUnavailable,
+            _ => ApiError::Internal,
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ApiError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        tracing::error!("Failed to sign a token: {}", err);
+        ApiError::Internal
+    }
+}
+
+impl From<AdminError> for ApiError {
+    fn from(err: AdminError) -> Self {
+        ApiError::Admin(err)
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(err: AuthError) -> Self {
+        ApiError::Auth(err)
+    }
+}
+
+impl From<ChallengeError> for ApiError {
+    fn from(err: ChallengeError) -> Self {
+        ApiError::Challenge(err)
+    }
+}
+
+impl From<CredentialError> for ApiError {
+    fn from(err: CredentialError) -> Self {
+        ApiError::Credential(err)
+    }
+}
+
+impl From<KeyError> for ApiError {
+    fn from(err: KeyError) -> Self {
+        ApiError::Key(err)
+    }
+}
+
+impl From<LnurlError> for ApiError {
+    fn from(err: LnurlError) -> Self {
+        ApiError::Lnurl(err)
+    }
+}
+
+impl From<NostrError> for ApiError {
+    fn from(err: NostrError) -> Self {
+        ApiError::Nostr(err)
+    }
+}
+
+impl From<ReportError> for ApiError {
+    fn from(err: ReportError) -> Self {
+        ApiError::Report(err)
+    }
+}
+
+impl From<StatusError> for ApiError {
+    fn from(err: StatusError) -> Self {
+        ApiError::Status(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            ApiError::InvalidInput(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::ProgramNotFound => (StatusCode::NOT_FOUND, "Program is not found"),
+            ApiError::DatabaseUnavailable => {
+                (StatusCode::SERVICE_UNAVAILABLE, "Database is unavailable")
+            }
+            ApiError::Internal => (StatusCode::INTERNAL_SERVER_ERROR, "Internal error"),
+            ApiError::Admin(err) => return err.into_response(),
+            ApiError::Auth(err) => return err.into_response(),
+            ApiError::Challenge(err) => return err.into_response(),
+            ApiError::Credential(err) => return err.into_response(),
+            ApiError::Key(err) => return err.into_response(),
+            ApiError::Lnurl(err) => return err.into_response(),
+            ApiError::Nostr(err) => return err.into_response(),
+            ApiError::Report(err) => return err.into_response(),
+            ApiError::Status(err) => return err.into_response(),
+        };
+
+        let body = Json(json!({
+            "error": error_message,
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_report_unavailable_database() {
+        let err = ApiError::from(sqlx::Error::PoolTimedOut);
+        assert_eq!(
+            StatusCode::SERVICE_UNAVAILABLE,
+            err.into_response().status()
+        );
+
+        let err = ApiError::from(sqlx::Error::RowNotFound);
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            err.into_response().status()
+        );
+    }
+
+    #[test]
+    fn should_keep_status_of_wrapped_errors() {
+        let err = ApiError::from(AuthError::ChallengeExpired);
+        assert_eq!(StatusCode::GONE, err.into_response().status());
+
+        // Wallets expect LUD-04 errors with 200.
+        let err = ApiError::from(LnurlError::ChallengeExpired);
+        assert_eq!(StatusCode::OK, err.into_response().status());
+    }
+}
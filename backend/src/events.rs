@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::broadcast;
+
+use crate::StatusSource;
+
+/// Published by the `user_statuses` trigger whenever a level changes,
+/// whether the scraper or the backend wrote it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusChanged {
+    pub user_id: i32,
+    pub program_id: i32,
+    pub previous_level: Option<i32>,
+    pub level: i32,
+    pub source: StatusSource,
+}
+
+/// Published by `/api/auth` once a wallet has signed a challenge.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginCompleted {
+    /// Hex encoded.
+    pub k1: String,
+}
+
+#[derive(Clone)]
+pub struct Events {
+    status_changed: broadcast::Sender<StatusChanged>,
+    login_completed: broadcast::Sender<LoginCompleted>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (status_changed, _) = broadcast::channel(256);
+        let (login_completed, _) = broadcast::channel(256);
+        Self {
+            status_changed,
+            login_completed,
+        }
+    }
+
+    pub fn subscribe_status_changed(&self) -> broadcast::Receiver<StatusChanged> {
+        self.status_changed.subscribe()
+    }
+
+    pub fn subscribe_login_completed(&self) -> broadcast::Receiver<LoginCompleted> {
+        self.login_completed.subscribe()
+    }
+
+    /// Starts forwarding Postgres notifications to subscribers until the
+    /// process exits, failing only when the listener cannot be set up.
+    ///
+    /// Every replica receives the notifications, whichever replica wrote.
+    pub async fn listen(self, pool: &PgPool) -> sqlx::Result<()> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener
+            .listen_all(["status_changed", "login_completed"])
+            .await?;
+
+        tokio::spawn(self.forward(listener));
+        Ok(())
+    }
+
+    async fn forward(self, mut listener: PgListener) {
+        loop {
+            match listener.recv().await {
+                // Nobody may be subscribed yet, so sending may fail.
+                Ok(notification) => match notification.channel() {
+                    "status_changed" => {
+                        if let Ok(event) = serde_json::from_str(notification.payload()) {
+                            let _ = self.status_changed.send(event);
+                        }
+                    }
+                    "login_completed" => {
+                        if let Ok(event) = serde_json::from_str(notification.payload()) {
+                            let _ = self.login_completed.send(event);
+                        }
+                    }
+                    _ => {}
+                },
+                // The listener reconnects on the next `recv`.
+                Err(err) => {
+                    tracing::warn!("Lost the notification listener: {}", err);
+                    tokio::time::sleep(Duration::from_secs(1)).await
+                }
+            }
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
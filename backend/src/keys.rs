@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+/// A signing key with the public half published in the JWKS.
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding: EncodingKey,
+    pub decoding: DecodingKey,
+    jwk: Jwk,
+}
+
+impl SigningKey {
+    /// Reads a PKCS#8 DER encoded P-256 (ES256) or Ed25519 (EdDSA) private key.
+    fn from_pkcs8(kid: String, der: &[u8]) -> anyhow::Result<Self> {
+        let (algorithm, encoding, parameters) =
+            if let Ok(pair) = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der) {
+                // An uncompressed point: 0x04 || x || y.
+                let (x, y) = pair.public_key().as_ref()[1..].split_at(32);
+                let parameters = AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: URL_SAFE_NO_PAD.encode(x),
+                    y: URL_SAFE_NO_PAD.encode(y),
+                });
+                (Algorithm::ES256, EncodingKey::from_ec_der(der), parameters)
+            } else if let Ok(pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+                let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+                });
+                (Algorithm::EdDSA, EncodingKey::from_ed_der(der), parameters)
+            } else {
+                bail!("Key {} must be a P-256 or Ed25519 key in PKCS#8.", kid);
+            };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                algorithm: Some(algorithm),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: parameters,
+        };
+
+        Ok(Self {
+            kid,
+            algorithm,
+            encoding,
+            decoding: DecodingKey::from_jwk(&jwk)?,
+            jwk,
+        })
+    }
+}
+
+/// Keys read from `JWT_KEYS`, e.g. `1:<base64>,2:<base64>`.
+///
+/// The key with the highest version signs; every listed key verifies, so a
+/// retired key keeps its tokens valid until they expire.
+pub struct Keys {
+    keys: BTreeMap<u32, SigningKey>,
+}
+
+impl Keys {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let keys = std::env::var("JWT_KEYS").context("JWT_KEYS must be set.")?;
+        Self::parse(&keys)
+    }
+
+    pub fn parse(keys: &str) -> anyhow::Result<Self> {
+        let keys = keys
+            .split(',')
+            .map(|entry| {
+                let (version, key) = entry
+                    .trim()
+                    .split_once(':')
+                    .context("A key must be formatted as <version>:<base64>.")?;
+                let version: u32 = version.parse()?;
+                let key = SigningKey::from_pkcs8(version.to_string(), &STANDARD.decode(key)?)?;
+                Ok((version, key))
+            })
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+        if keys.is_empty() {
+            bail!("At least one key is required.");
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn current(&self) -> &SigningKey {
+        self.keys.values().next_back().unwrap()
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&SigningKey> {
+        self.keys.values().find(|key| key.kid == kid)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.values().map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
+    use serde::{Deserialize, Serialize};
+
+    const ES256_KEY: &str = "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg+EGlKsa07DRHKQTup1jBO73fsrCC7QFymJiGIO9m/FahRANCAAQrBnZfYzyCCxUIPlaK7t/MDQq6rwYk2T+5HN+vM0pQFBre7eUQ5+s0tCecR7ivhLdPUotO0wGc7u4VDBvvaAXw";
+    const EDDSA_KEY: &str = "MC4CAQAwBQYDK2VwBCIEIEPtnvFOx8YRPsZnUYMWO5jJNWT3DlqD2+9UKzSkqIgN";
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: i64,
+    }
+
+    fn sign(key: &SigningKey) -> String {
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        let claims = Claims {
+            sub: "02aa".to_string(),
+            exp: i64::MAX,
+        };
+        encode(&header, &claims, &key.encoding).unwrap()
+    }
+
+    fn verify(keys: &Keys, token: &str) -> jsonwebtoken::errors::Result<Claims> {
+        let kid = decode_header(token)?.kid.unwrap();
+        let key = keys.get(&kid).unwrap();
+        Ok(decode(token, &key.decoding, &Validation::new(key.algorithm))?.claims)
+    }
+
+    #[test]
+    fn should_sign_with_highest_version() {
+        let keys = Keys::parse(&format!("1:{},2:{}", ES256_KEY, EDDSA_KEY)).unwrap();
+        assert_eq!("2", keys.current().kid);
+        assert_eq!(Algorithm::EdDSA, keys.current().algorithm);
+    }
+
+    #[test]
+    fn should_verify_tokens_of_retired_keys() {
+        let old = Keys::parse(&format!("1:{}", ES256_KEY)).unwrap();
+        let token = sign(old.current());
+
+        let rotated = Keys::parse(&format!("1:{},2:{}", ES256_KEY, EDDSA_KEY)).unwrap();
+        assert_eq!("02aa", verify(&rotated, &token).unwrap().sub);
+        assert_eq!(
+            "02aa",
+            verify(&rotated, &sign(rotated.current())).unwrap().sub
+        );
+    }
+
+    #[test]
+    fn should_verify_with_published_jwks() {
+        let keys = Keys::parse(&format!("1:{},2:{}", ES256_KEY, EDDSA_KEY)).unwrap();
+        let jwks: JwkSet =
+            serde_json::from_str(&serde_json::to_string(&keys.jwks()).unwrap()).unwrap();
+
+        for key in keys.keys.values() {
+            let token = sign(key);
+            let jwk = jwks.find(&key.kid).unwrap();
+            let decoding = DecodingKey::from_jwk(jwk).unwrap();
+            assert!(decode::<Claims>(&token, &decoding, &Validation::new(key.algorithm)).is_ok());
+        }
+    }
+
+    #[test]
+    fn should_reject_malformed_keys() {
+        assert!(Keys::parse("").is_err());
+        assert!(Keys::parse(ES256_KEY).is_err());
+        assert!(Keys::parse("1:c2hvcnQ=").is_err());
+    }
+}
@@ -1,20 +1,49 @@
-use std::{path, vec};
-use tower_http::services::ServeDir;
+use std::{convert::Infallible, path, sync::Arc, vec};
+use tower_http::{services::ServeDir, trace::TraceLayer};
 
 use axum::{
-    extract::{FromRef, Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::get,
+    extract::{DefaultBodyLimit, FromRef, Path, Query, State, TypedHeader},
+    headers::UserAgent,
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use bech32::ToBase32;
-use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use chrono::{DateTime, NaiveDate, Utc};
+use confidence::{Confidence, Tally, HALF_LIFE_DAYS, UNDATED_WEIGHT};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+mod admin;
 mod auth;
+mod credentials;
+mod error;
+pub mod events;
+pub mod keys;
+pub mod lnurl;
+mod nostr;
+mod recommendations;
+mod reports;
+mod sessions;
+pub mod status_challenges;
+mod statuses;
+#[cfg(test)]
+mod testing;
+mod user_keys;
 use auth::{AuthError, Claims};
+use credentials::LoginTests;
+use error::ApiError;
+use events::Events;
+use keys::Keys;
+use lnurl::VerifiedLogin;
+use nostr::NostrError;
+use scraper::vault::Keyring;
+use statuses::EvidenceDir;
+use user_keys::KeyType;
 
 type Challenge = String;
 type ServiceUrl = String;
@@ -23,45 +52,108 @@ type ServiceUrl = String;
 struct AppState {
     pool: PgPool,
     service_url: ServiceUrl,
+    events: Events,
+    evidence_dir: EvidenceDir,
+    keys: Arc<Keys>,
+    keyring: Arc<Keyring>,
+    login_tests: LoginTests,
 }
 
-#[derive(Serialize, sqlx::Type)]
+#[derive(Debug, Serialize, sqlx::Type)]
 struct Program {
     id: i32,
     name: String,
 }
 
-#[derive(Serialize, sqlx::Type)]
+#[derive(Debug, Serialize, sqlx::Type)]
 struct Status {
     program_id: i32,
     level: i32,
     name: String,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "status_source", rename_all = "lowercase")]
+pub enum StatusSource {
+    Verified,
+    Manual,
+}
+
 #[derive(Serialize)]
 struct Link {
     program: String,
     status: String,
+    confidence: Confidence,
 }
 
+#[derive(Deserialize)]
+struct LinkQuery {
+    /// Only reports submitted within this many months count, which leaves out undated ones.
+    months: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct ProgramDetail {
+    id: i32,
+    name: String,
+    /// From the lowest level to the highest.
+    statuses: Vec<StatusLadderStep>,
+    reports: ReportCounts,
+}
+
+#[derive(Serialize)]
+struct StatusLadderStep {
+    level: i32,
+    name: String,
+    /// Users whose current status is this level, leaving out lapsed declarations.
+    tracked_users: i64,
+}
+
+#[derive(Serialize)]
+struct ReportCounts {
+    /// Approved reports of matching into the program.
+    incoming: i64,
+    /// Approved reports of matching from the program into another.
+    outgoing: i64,
+}
+
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 100;
+
 #[derive(Deserialize)]
 struct SearchQuery {
     text: String,
+    limit: Option<usize>,
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    programs: Vec<Program>,
+    /// Absent on the last page.
+    next_cursor: Option<String>,
 }
 
 #[derive(Serialize)]
 struct UserStatus {
     program: Program,
     status: Status,
+    source: StatusSource,
+    /// Only declared for manual statuses.
+    expires_on: Option<NaiveDate>,
+    /// Whether `expires_on` has passed, after which the status no longer
+    /// counts towards recommendations and the ladder of the program.
+    expired: bool,
+    has_evidence: bool,
 }
 
 async fn get_user_statuses(
-    Claims { sub, .. }: Claims,
+    Claims { user_id, .. }: Claims,
     State(pool): State<PgPool>,
-) -> impl IntoResponse {
-    let mut conn = pool.acquire().await.unwrap();
-
-    let pubkey = hex::decode(&sub).unwrap();
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
 
     let user_statuses = sqlx::query_as!(
         UserStatus,
@@ -75,7 +167,11 @@ async fn get_user_statuses(
                 program_statuses.program_id,
                 program_statuses.level,
                 program_statuses.name
-            ) AS "status!: Status"
+            ) AS "status!: Status",
+            user_statuses.source AS "source: StatusSource",
+            user_statuses.expires_on,
+            COALESCE(user_statuses.expires_on < CURRENT_DATE, FALSE) AS "expired!",
+            user_statuses.evidence_file IS NOT NULL AS "has_evidence!"
         FROM user_statuses
         INNER JOIN program_statuses
             ON user_statuses.program_id = program_statuses.program_id
@@ -83,68 +179,228 @@ async fn get_user_statuses(
         INNER JOIN programs
             ON program_statuses.program_id = programs.id
         WHERE
-            user_statuses.user_pubkey = $1
+            user_statuses.user_id = $1
         ORDER BY
             program_statuses.level
         "#,
-        &pubkey,
+        user_id,
     )
     .fetch_all(&mut conn)
-    .await
-    .unwrap();
+    .await?;
 
     let resp = json!(user_statuses);
 
-    (StatusCode::OK, Json(resp))
+    Ok((StatusCode::OK, Json(resp)))
+}
+
+#[derive(Deserialize)]
+struct StatusHistoryQuery {
+    program_id: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct StatusHistoryEntry {
+    program: Program,
+    status: Status,
+    previous_status: Option<String>,
+    source: StatusSource,
+    recorded_at: DateTime<Utc>,
+}
+
+async fn get_user_status_history(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Query(StatusHistoryQuery { program_id }): Query<StatusHistoryQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let history = sqlx::query_as!(
+        StatusHistoryEntry,
+        r#"
+        SELECT
+            (
+                programs.id,
+                programs.name
+            ) AS "program!: Program",
+            (
+                program_statuses.program_id,
+                program_statuses.level,
+                program_statuses.name
+            ) AS "status!: Status",
+            previous_statuses.name AS "previous_status?",
+            user_status_history.source AS "source: StatusSource",
+            user_status_history.recorded_at
+        FROM user_status_history
+        INNER JOIN program_statuses
+            ON user_status_history.program_id = program_statuses.program_id
+            AND user_status_history.level = program_statuses.level
+        LEFT JOIN program_statuses AS previous_statuses
+            ON user_status_history.program_id = previous_statuses.program_id
+            AND user_status_history.previous_level = previous_statuses.level
+        INNER JOIN programs
+            ON user_status_history.program_id = programs.id
+        WHERE
+            user_status_history.user_id = $1
+            AND ($2::INT IS NULL OR user_status_history.program_id = $2)
+        ORDER BY
+            user_status_history.recorded_at DESC,
+            user_status_history.id DESC
+        "#,
+        user_id,
+        program_id,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    Ok((StatusCode::OK, Json(history)))
+}
+
+/// Streams a `status_changed` event whenever a level of the user changes,
+/// whether the scraper, a declaration or an admin changed it.
+async fn get_user_status_events(
+    Claims { user_id, .. }: Claims,
+    State(events): State<Events>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let status_changed = events.subscribe_status_changed();
+
+    // A subscriber that lags behind misses events rather than the stream ending.
+    let stream = BroadcastStream::new(status_changed).filter_map(move |event| match event {
+        Ok(event) if event.user_id == user_id => Event::default()
+            .event("status_changed")
+            .json_data(event)
+            .ok()
+            .map(Ok),
+        _ => None,
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
 async fn login(
     State(service_url): State<ServiceUrl>,
     State(pool): State<PgPool>,
-) -> impl IntoResponse {
-    let challenge: [u8; 32] = rand::random();
-    let mut conn = pool.acquire().await.unwrap();
-    sqlx::query!("INSERT INTO challenges (challenge) VALUES($1)", &challenge)
-        .fetch_optional(&mut conn)
-        .await
-        .unwrap();
-
-    let k1 = hex::encode(challenge);
-    let url = format!("{}/api/auth?tag=login&k1={}", &service_url, &k1);
-    let encoded = bech32::encode("lnurl", url.to_base32(), bech32::Variant::Bech32).unwrap();
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+    let k1 = hex::encode(lnurl::create_challenge(&mut conn, None).await?);
+    let encoded = lnurl::encode_lnurl(&service_url, &k1);
 
     let resp = json!({
         "lnurl": encoded,
         "k1": k1,
     });
 
-    (StatusCode::OK, Json(resp))
+    Ok((StatusCode::OK, Json(resp)))
 }
 
+/// Issues a token once the challenge has been signed.
+///
+/// Each challenge yields a single token, so a leaked k1 cannot mint more.
 async fn get_login_status(
     State(pool): State<PgPool>,
+    State(keys): State<Arc<Keys>>,
     Path(k1): Path<Challenge>,
-) -> impl IntoResponse {
-    let k1 = hex::decode(&k1).unwrap();
-    let mut conn = pool.acquire().await.unwrap();
-    let pubkey = sqlx::query_scalar!(
+    user_agent: Option<TypedHeader<UserAgent>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let k1 = hex::decode(&k1).map_err(|_| AuthError::InvalidChallenge)?;
+    let mut conn = pool.acquire().await?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+        UPDATE challenges
+        SET consumed_at = NOW()
+        WHERE
+            challenge = $1
+            AND user_id IS NOT NULL
+            AND consumed_at IS NULL
+            AND expires_at > NOW()
+        RETURNING user_id AS "user_id!"
+        "#,
+        &k1,
+    )
+    .fetch_optional(&mut conn)
+    .await?;
+
+    if let Some(user_id) = user_id {
+        let user_agent = user_agent.map(|TypedHeader(user_agent)| user_agent.to_string());
+        return auth::authorize(&mut conn, &keys, user_id, user_agent).await;
+    }
+
+    let challenge = sqlx::query!(
         r#"
-        SELECT user_pubkey AS "pubkey!" FROM challenges WHERE challenge = $1
+        SELECT
+            consumed_at IS NOT NULL AS "consumed!",
+            expires_at <= NOW() AS "expired!"
+        FROM challenges
+        WHERE challenge = $1
         "#,
         &k1,
     )
     .fetch_optional(&mut conn)
-    .await
-    .unwrap();
-
-    if let Some(pubkey) = pubkey {
-        let auth = auth::authorize(pubkey).unwrap();
-        auth.into_response()
-    } else {
-        AuthError::WaitingForLogin.into_response()
+    .await?;
+
+    match challenge {
+        Some(challenge) if challenge.consumed => Err(AuthError::ChallengeConsumed.into()),
+        Some(challenge) if !challenge.expired => Err(AuthError::WaitingForLogin.into()),
+        // Swept or never issued.
+        _ => Err(AuthError::ChallengeExpired.into()),
     }
 }
 
+/// Streams an `authenticated` event as soon as the challenge has been signed,
+/// after which the token of a login can be picked up from `/api/login/:k1`,
+/// or an `expired` event once the challenge can no longer be signed.
+async fn get_login_events(
+    State(pool): State<PgPool>,
+    State(events): State<Events>,
+    Path(k1): Path<Challenge>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let k1 = hex::decode(&k1).map_err(|_| AuthError::InvalidChallenge)?;
+
+    // Subscribe before reading the challenge so that a login in between is not missed.
+    let login_completed = events.subscribe_login_completed();
+
+    let mut conn = pool.acquire().await?;
+    let challenge = sqlx::query!(
+        r#"
+        SELECT
+            user_id IS NOT NULL OR consumed_at IS NOT NULL AS "signed!",
+            expires_at
+        FROM challenges
+        WHERE challenge = $1
+        "#,
+        &k1,
+    )
+    .fetch_optional(&mut conn)
+    .await?;
+
+    let (signed, expires_in) = match challenge {
+        Some(challenge) => match (challenge.expires_at - Utc::now()).to_std() {
+            Ok(expires_in) => (challenge.signed, expires_in),
+            Err(_) => return Err(AuthError::ChallengeExpired.into()),
+        },
+        None => return Err(AuthError::ChallengeExpired.into()),
+    };
+
+    let k1 = hex::encode(k1);
+    let completed = BroadcastStream::new(login_completed).filter_map(move |event| match event {
+        Ok(event) if event.k1 == k1 => Some(()),
+        _ => None,
+    });
+    let authenticated = tokio_stream::iter(signed.then_some(()))
+        .chain(completed)
+        .map(|()| Event::default().event("authenticated").data("{}"));
+
+    let deadline = tokio::time::Instant::now() + expires_in;
+    let expired = futures_util::stream::once(async move {
+        tokio::time::sleep_until(deadline).await;
+        Event::default().event("expired").data("{}")
+    });
+
+    let stream = authenticated.merge(expired).take(1).map(Ok);
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
 #[derive(Deserialize)]
 struct LnurlAuth {
     k1: String,
@@ -155,87 +411,220 @@ struct LnurlAuth {
 async fn auth(
     State(pool): State<PgPool>,
     Query(LnurlAuth { k1, sig, key }): Query<LnurlAuth>,
-) -> impl IntoResponse {
-    let k1 = hex::decode(&k1).unwrap();
-    let sig = hex::decode(&sig).unwrap();
-    let key = hex::decode(&key).unwrap();
+) -> Result<impl IntoResponse, ApiError> {
+    // Nothing is written unless the wallet holds the key.
+    let VerifiedLogin { k1, pubkey } = lnurl::verify(&k1, &sig, &key)?;
 
-    {
-        let mut trans = pool.begin().await.unwrap();
+    let mut trans = pool.begin().await?;
+    lnurl::sign_challenge(&mut trans, &k1, &pubkey, KeyType::Lnurl).await?;
+    trans.commit().await?;
 
-        let count = sqlx::query_scalar!(
-            "SELECT COUNT(challenge) FROM challenges WHERE challenge = $1",
-            &k1
-        )
-        .fetch_one(&mut trans)
-        .await
-        .unwrap();
-
-        if let Some(0) = count {
-            let resp = json!({
-                "status": "ERROR",
-                "reason": "Challenge is not found.",
-            });
-            return (StatusCode::OK, Json(resp));
-        }
-
-        sqlx::query!(
-            "INSERT INTO users (pubkey) VALUES ($1) ON CONFLICT DO NOTHING",
-            &key
-        )
-        .fetch_optional(&mut trans)
-        .await
-        .unwrap();
+    let resp = json!({
+        "status": "OK",
+    });
+    Ok((StatusCode::OK, Json(resp)))
+}
 
-        sqlx::query!(
-            "UPDATE challenges SET user_pubkey = $1 WHERE challenge = $2",
-            &key,
-            &k1,
-        )
-        .fetch_optional(&mut trans)
+/// Signs a challenge with a NIP-98 event instead of a wallet, e.g. through a
+/// NIP-07 browser extension.
+///
+/// As with LNURL-auth, the token is then picked up from `/api/login/:k1`.
+async fn nostr_auth(
+    State(pool): State<PgPool>,
+    State(service_url): State<ServiceUrl>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let url = format!("{}/api/auth/nostr", service_url);
+    let nostr::VerifiedLogin { k1, pubkey } = nostr::verify(
+        nostr::authorization(&headers)?,
+        &url,
+        "POST",
+        Utc::now().timestamp(),
+    )?;
+
+    let mut trans = pool.begin().await?;
+    lnurl::sign_challenge(&mut trans, &k1, &pubkey, KeyType::Nostr)
         .await
-        .unwrap();
+        .map_err(|err| match err {
+            // Reported like the other failures of the event rather than as LUD-04.
+            ApiError::Lnurl(err) => NostrError::from(err).into(),
+            err => err,
+        })?;
+    trans.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
 
-        trans.commit().await.unwrap();
+async fn search_programs(
+    State(pool): State<PgPool>,
+    Query(SearchQuery {
+        text,
+        limit,
+        cursor,
+    }): Query<SearchQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    if !(1..=MAX_SEARCH_LIMIT).contains(&limit) {
+        return Err(ApiError::InvalidInput("limit must be between 1 and 100"));
+    }
+    // The cursor is the offset into the ranking, opaque to clients.
+    let offset: usize = match cursor {
+        Some(cursor) => cursor
+            .parse()
+            .map_err(|_| ApiError::InvalidInput("cursor is invalid"))?,
+        None => 0,
+    };
+
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok((
+            StatusCode::OK,
+            Json(SearchResult {
+                programs: vec![],
+                next_cursor: None,
+            }),
+        ));
     }
 
-    let secp = Secp256k1::verification_only();
-    let msg = Message::from_slice(&k1).unwrap();
-    let sig = Signature::from_der(&sig).unwrap();
-    let pk = PublicKey::from_slice(&key).unwrap();
+    let mut conn = pool.acquire().await?;
+
+    // The trigram indexes narrow the programs down to those `search::rank` keeps.
+    let candidates = sqlx::query!(
+        r#"
+        SELECT
+            programs.id,
+            programs.name,
+            ARRAY(
+                SELECT alias FROM program_aliases WHERE program_id = programs.id
+            ) AS "aliases!"
+        FROM programs
+        WHERE
+            programs.name % $1
+            OR programs.name ILIKE $2
+            OR programs.id IN (
+                SELECT program_id
+                FROM program_aliases
+                WHERE alias % $1 OR alias ILIKE $2
+            )
+        "#,
+        text,
+        format!("%{}%", escape_like(text)),
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    let ranked = search::rank(
+        text,
+        candidates,
+        |program| &program.name,
+        |program| program.aliases.iter().map(String::as_str).collect(),
+    );
+    let next_cursor = (ranked.len() > offset + limit).then(|| (offset + limit).to_string());
+    let programs = ranked
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|program| Program {
+            id: program.id,
+            name: program.name,
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(SearchResult {
+            programs,
+            next_cursor,
+        }),
+    ))
+}
+
+/// Escapes the wildcards of LIKE so that they match literally.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
 
-    secp.verify_ecdsa(&msg, &sig, &pk).unwrap();
+async fn ensure_program(conn: &mut PgConnection, id: i32) -> Result<(), ApiError> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM programs WHERE id = $1) AS "exists!""#,
+        id,
+    )
+    .fetch_one(conn)
+    .await?;
 
-    let resp = json!({
-        "status": "OK",
-    });
-    (StatusCode::OK, Json(resp))
+    if !exists {
+        return Err(ApiError::ProgramNotFound);
+    }
+    Ok(())
 }
 
-async fn search_programs(
+async fn get_program(
     State(pool): State<PgPool>,
-    Query(SearchQuery { text }): Query<SearchQuery>,
-) -> impl IntoResponse {
-    if text.trim().is_empty() {
-        return (StatusCode::OK, Json(vec![]));
-    }
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
 
-    let mut conn = pool.acquire().await.unwrap();
+    let program = sqlx::query_as!(Program, "SELECT id, name FROM programs WHERE id = $1", id)
+        .fetch_optional(&mut conn)
+        .await?
+        .ok_or(ApiError::ProgramNotFound)?;
 
-    let programs = sqlx::query_as!(
-        Program,
-        "SELECT * FROM programs WHERE LOWER(name) LIKE LOWER($1)",
-        format!("%{}%", text.trim()),
+    let statuses = sqlx::query_as!(
+        StatusLadderStep,
+        r#"
+        SELECT
+            program_statuses.level,
+            program_statuses.name,
+            COUNT(user_statuses.user_id) AS "tracked_users!"
+        FROM program_statuses
+        LEFT JOIN user_statuses
+            ON program_statuses.program_id = user_statuses.program_id
+            AND program_statuses.level = user_statuses.level
+            AND (user_statuses.expires_on IS NULL OR user_statuses.expires_on >= CURRENT_DATE)
+        WHERE program_statuses.program_id = $1
+        GROUP BY program_statuses.level, program_statuses.name
+        ORDER BY program_statuses.level
+        "#,
+        id,
     )
     .fetch_all(&mut conn)
-    .await
-    .unwrap();
+    .await?;
 
-    (StatusCode::OK, Json(programs))
+    let reports = sqlx::query_as!(
+        ReportCounts,
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE to_program_id = $1) AS "incoming!",
+            COUNT(*) FILTER (WHERE from_program_id = $1) AS "outgoing!"
+        FROM reports
+        WHERE
+            (from_program_id = $1 OR to_program_id = $1)
+            AND state = 'approved'
+        "#,
+        id,
+    )
+    .fetch_one(&mut conn)
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ProgramDetail {
+            id: program.id,
+            name: program.name,
+            statuses,
+            reports,
+        }),
+    ))
 }
 
-async fn get_statuses(State(pool): State<PgPool>, Path(id): Path<i32>) -> impl IntoResponse {
-    let mut conn = pool.acquire().await.unwrap();
+async fn get_statuses(
+    State(pool): State<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+    ensure_program(&mut conn, id).await?;
 
     let statuses = sqlx::query_as!(
         Status,
@@ -243,63 +632,193 @@ async fn get_statuses(State(pool): State<PgPool>, Path(id): Path<i32>) -> impl I
         id,
     )
     .fetch_all(&mut conn)
-    .await
-    .unwrap();
-    (StatusCode::OK, Json(statuses))
+    .await?;
+    Ok((StatusCode::OK, Json(statuses)))
 }
 
 async fn diagnose_links(
     State(pool): State<PgPool>,
     Path((id, level)): Path<(i32, i32)>,
-) -> impl IntoResponse {
-    let mut conn = pool.acquire().await.unwrap();
+    Query(LinkQuery { months }): Query<LinkQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if matches!(months, Some(months) if months <= 0) {
+        return Err(ApiError::InvalidInput("months must be positive"));
+    }
+
+    let mut conn = pool.acquire().await?;
+    ensure_program(&mut conn, id).await?;
 
-    let links = sqlx::query_as!(
-        Link,
+    // Every target program links to its highest status that has been matched.
+    let rows = sqlx::query!(
         r#"
-            SELECT
-                (SELECT
-                    name
-                    FROM programs
-                    WHERE id = to_program_id
-                ) AS "program!",
-                (SELECT
-                    name
-                    FROM program_statuses
-                    WHERE program_id = to_program_id
-                    AND level = MAX(to_status_level)
-                ) AS "status!"
-            FROM reports 
-            WHERE 
-                result = 'match'
-                AND from_program_id = $1
-                AND from_status_level <= $2
-            GROUP BY to_program_id
+            WITH tallies AS (
+                SELECT
+                    to_program_id,
+                    to_status_level,
+                    COUNT(*) FILTER (WHERE result = 'match') AS matches,
+                    COUNT(*) FILTER (WHERE result = 'deny') AS denies,
+                    COUNT(*) FILTER (WHERE result = 'challenge') AS challenges,
+                    SUM(weight) FILTER (WHERE result = 'match') AS weighted_matches,
+                    SUM(weight) FILTER (WHERE result = 'deny') AS weighted_denies,
+                    SUM(weight) FILTER (WHERE result = 'challenge') AS weighted_challenges
+                FROM weighted_reports($3, $5, $4)
+                WHERE from_program_id = $1 AND from_status_level <= $2
+                GROUP BY to_program_id, to_status_level
+            )
+            SELECT DISTINCT ON (tallies.to_program_id)
+                programs.name AS program,
+                program_statuses.name AS status,
+                tallies.matches AS "matches!",
+                tallies.denies AS "denies!",
+                tallies.challenges AS "challenges!",
+                COALESCE(tallies.weighted_matches, 0) AS "weighted_matches!",
+                COALESCE(tallies.weighted_denies, 0) AS "weighted_denies!",
+                COALESCE(tallies.weighted_challenges, 0) AS "weighted_challenges!"
+            FROM tallies
+            INNER JOIN programs
+                ON tallies.to_program_id = programs.id
+            INNER JOIN program_statuses
+                ON tallies.to_program_id = program_statuses.program_id
+                AND tallies.to_status_level = program_statuses.level
+            WHERE tallies.matches > 0
+            ORDER BY tallies.to_program_id, tallies.to_status_level DESC
         "#,
         id,
-        level
+        level,
+        HALF_LIFE_DAYS,
+        months,
+        UNDATED_WEIGHT,
     )
     .fetch_all(&mut conn)
-    .await
-    .unwrap();
-    (StatusCode::OK, Json(links))
+    .await?;
+
+    let mut links: Vec<_> = rows
+        .into_iter()
+        .map(|row| Link {
+            program: row.program,
+            status: row.status,
+            confidence: Tally {
+                matches: row.matches,
+                denies: row.denies,
+                challenges: row.challenges,
+                weighted_matches: row.weighted_matches,
+                weighted_denies: row.weighted_denies,
+                weighted_challenges: row.weighted_challenges,
+            }
+            .into(),
+        })
+        .collect();
+    links.sort_by(|a, b| {
+        b.confidence
+            .probability
+            .total_cmp(&a.confidence.probability)
+    });
+
+    Ok((StatusCode::OK, Json(links)))
+}
+
+/// Public keys that verify our access tokens, for services that accept them.
+async fn get_jwks(State(keys): State<Arc<Keys>>) -> impl IntoResponse {
+    (StatusCode::OK, Json(keys.jwks()))
 }
 
-pub fn router(service_url: &str, pool: PgPool, static_folder: impl AsRef<path::Path>) -> Router {
+pub fn router(
+    service_url: &str,
+    pool: PgPool,
+    events: Events,
+    static_folder: impl AsRef<path::Path>,
+    evidence_dir: impl AsRef<path::Path>,
+    keys: Keys,
+    keyring: Keyring,
+) -> Router {
     Router::new()
+        .route("/.well-known/jwks.json", get(get_jwks))
         .route("/api/login", get(login))
         .route("/api/login/:k1", get(get_login_status))
+        .route("/api/login/:k1/events", get(get_login_events))
         .route("/api/auth", get(auth))
+        .route("/api/auth/nostr", post(nostr_auth))
+        .route("/api/token/refresh", post(sessions::refresh_token))
+        .route("/api/logout", post(sessions::logout))
+        .route("/api/user/sessions", get(sessions::list_sessions))
+        .route(
+            "/api/user/keys",
+            get(user_keys::list_keys).post(user_keys::link_key),
+        )
+        .route("/api/user/keys/:pubkey", delete(user_keys::unlink_key))
+        .route("/api/user/sessions/:id", delete(sessions::revoke_session))
         .route("/api/user/statuses", get(get_user_statuses))
+        .route(
+            "/api/user/recommendations",
+            get(recommendations::get_recommendations),
+        )
+        .route("/api/user/statuses/history", get(get_user_status_history))
+        .route("/api/user/statuses/events", get(get_user_status_events))
+        .route(
+            "/api/user/statuses/:program_id",
+            // Leaves room for the other fields next to the evidence.
+            put(statuses::declare_status).layer(DefaultBodyLimit::max(
+                statuses::MAX_EVIDENCE_BYTES + 64 * 1024,
+            )),
+        )
+        .route(
+            "/api/user/statuses/:program_id/evidence",
+            get(statuses::get_evidence),
+        )
+        .route(
+            "/api/user/credentials",
+            get(credentials::list_credentials).post(credentials::add_credential),
+        )
+        .route(
+            "/api/user/credentials/:program_id",
+            put(credentials::update_credential).delete(credentials::delete_credential),
+        )
+        .route(
+            "/api/user/credentials/:program_id/test",
+            post(credentials::test_credential),
+        )
+        .route(
+            "/api/user/challenges",
+            get(status_challenges::list_challenges).post(status_challenges::add_challenge),
+        )
+        .route(
+            "/api/user/challenges/:id/progress",
+            put(status_challenges::update_progress),
+        )
+        .route("/api/reports", post(reports::submit_report))
+        .route("/api/user/reports", get(reports::list_reports))
         .route("/api/programs/search", get(search_programs))
+        .route("/api/programs/:id", get(get_program))
         .route("/api/programs/:id/statuses", get(get_statuses))
         .route(
             "/api/programs/:id/statuses/:level/links",
             get(diagnose_links),
         )
+        .route("/api/admin/programs/:id", put(admin::rename_program))
+        .route(
+            "/api/admin/programs/:id/statuses/:level",
+            put(admin::put_status),
+        )
+        .route(
+            "/api/admin/programs/:id/statuses/:level/merge",
+            post(admin::merge_status),
+        )
+        .route("/api/admin/reports", get(admin::list_reports))
+        .route("/api/admin/reports/:id", put(admin::moderate_report))
+        .route(
+            "/api/admin/scrape-failures",
+            get(admin::list_scrape_failures),
+        )
+        .route("/api/admin/audit-log", get(admin::list_audit_log))
         .merge(Router::new().nest_service("/", ServeDir::new(static_folder)))
+        .layer(TraceLayer::new_for_http())
         .with_state(AppState {
             service_url: service_url.to_string(),
             pool,
+            events,
+            evidence_dir: evidence_dir.as_ref().to_path_buf(),
+            keys: Arc::new(keys),
+            keyring: Arc::new(keyring),
+            login_tests: LoginTests::default(),
         })
 }
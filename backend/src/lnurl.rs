@@ -0,0 +1,420 @@
+use std::time::Duration;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use bech32::ToBase32;
+use chrono::{DateTime, Utc};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+
+use crate::{error::ApiError, user_keys::KeyType};
+
+/// How long a wallet has to sign a challenge and the page to pick up the token.
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Failures reported to the wallet as LUD-04 `{"status":"ERROR","reason":...}`.
+#[derive(Debug, PartialEq)]
+pub enum LnurlError {
+    InvalidK1,
+    InvalidSignature,
+    InvalidKey,
+    VerificationFailed,
+    ChallengeNotFound,
+    ChallengeExpired,
+    ChallengeAlreadyUsed,
+    KeyInUse,
+}
+
+impl LnurlError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LnurlError::InvalidK1 => "k1 must be 32 bytes in hex.",
+            LnurlError::InvalidSignature => "sig must be a DER encoded signature in hex.",
+            LnurlError::InvalidKey => "key must be a secp256k1 public key in hex.",
+            LnurlError::VerificationFailed => "Signature does not match the key.",
+            LnurlError::ChallengeNotFound => "Challenge is not found.",
+            LnurlError::ChallengeExpired => "Challenge has expired.",
+            LnurlError::ChallengeAlreadyUsed => "Challenge has already been used.",
+            LnurlError::KeyInUse => "Key already belongs to an account.",
+        }
+    }
+}
+
+impl IntoResponse for LnurlError {
+    fn into_response(self) -> Response {
+        let body = Json(json!({
+            "status": "ERROR",
+            "reason": self.reason(),
+        }));
+
+        // Wallets read the status from the body, not from the HTTP status.
+        (StatusCode::OK, body).into_response()
+    }
+}
+
+/// A login whose signature has been verified against its challenge.
+#[derive(Debug)]
+pub struct VerifiedLogin {
+    pub k1: Vec<u8>,
+    /// The compressed public key, whatever encoding the wallet sent.
+    pub pubkey: Vec<u8>,
+}
+
+/// Checks that `sig` is a signature of `k1` by `key`, all of them hex encoded.
+pub fn verify(k1: &str, sig: &str, key: &str) -> Result<VerifiedLogin, LnurlError> {
+    let k1 = hex::decode(k1).map_err(|_| LnurlError::InvalidK1)?;
+    let msg = Message::from_slice(&k1).map_err(|_| LnurlError::InvalidK1)?;
+    let sig = hex::decode(sig)
+        .ok()
+        .and_then(|sig| Signature::from_der(&sig).ok())
+        .ok_or(LnurlError::InvalidSignature)?;
+    let pk = hex::decode(key)
+        .ok()
+        .and_then(|key| PublicKey::from_slice(&key).ok())
+        .ok_or(LnurlError::InvalidKey)?;
+
+    Secp256k1::verification_only()
+        .verify_ecdsa(&msg, &sig, &pk)
+        .map_err(|_| LnurlError::VerificationFailed)?;
+
+    Ok(VerifiedLogin {
+        k1,
+        pubkey: pk.serialize().to_vec(),
+    })
+}
+
+/// A row of `challenges`.
+pub struct ChallengeRow {
+    /// Whether a key has signed the challenge, or a token has been issued for it.
+    pub used: bool,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Checks that the challenge found for a login, if any, can still be signed.
+pub fn check_challenge(
+    challenge: Option<ChallengeRow>,
+    now: DateTime<Utc>,
+) -> Result<(), LnurlError> {
+    match challenge {
+        None => Err(LnurlError::ChallengeNotFound),
+        Some(ChallengeRow { used: true, .. }) => Err(LnurlError::ChallengeAlreadyUsed),
+        Some(ChallengeRow { expires_at, .. }) if expires_at <= now => {
+            Err(LnurlError::ChallengeExpired)
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Issues a challenge that signs in, or that adds the signing key to the
+/// account of `link_user_id`, and returns its k1.
+pub async fn create_challenge(
+    conn: &mut PgConnection,
+    link_user_id: Option<i32>,
+) -> Result<[u8; 32], ApiError> {
+    let challenge: [u8; 32] = rand::random();
+    sqlx::query!(
+        r#"
+        INSERT INTO challenges (challenge, link_user_id, expires_at)
+        VALUES ($1, $2, NOW() + make_interval(secs => $3))
+        "#,
+        &challenge,
+        link_user_id,
+        CHALLENGE_TTL.as_secs_f64(),
+    )
+    .execute(conn)
+    .await?;
+    Ok(challenge)
+}
+
+/// The bech32 encoded `/api/auth` callback that wallets scan.
+pub fn encode_lnurl(service_url: &str, k1: &str) -> String {
+    let url = format!("{}/api/auth?tag=login&k1={}", service_url, k1);
+    bech32::encode("lnurl", url.to_base32(), bech32::Variant::Bech32).unwrap()
+}
+
+/// Completes the challenge that `pubkey` has signed: signs in to the account
+/// of the key, or links the key to the account that issued the challenge.
+pub async fn sign_challenge(
+    trans: &mut Transaction<'_, Postgres>,
+    k1: &[u8],
+    pubkey: &[u8],
+    key_type: KeyType,
+) -> Result<(), ApiError> {
+    match lock_challenge(trans, k1).await? {
+        None => complete_login(trans, k1, pubkey, key_type).await?,
+        Some(user_id) => link_key(trans, k1, pubkey, key_type, user_id).await?,
+    }
+
+    // Delivered to every replica on commit.
+    sqlx::query!(
+        "SELECT pg_notify('login_completed', json_build_object('k1', encode($1, 'hex'))::TEXT)",
+        k1,
+    )
+    .execute(&mut *trans)
+    .await?;
+
+    Ok(())
+}
+
+/// Locks the challenge until `trans` ends, checks that it can still be signed
+/// and returns the account it links keys to, if any.
+async fn lock_challenge(
+    trans: &mut Transaction<'_, Postgres>,
+    k1: &[u8],
+) -> Result<Option<i32>, ApiError> {
+    let challenge = sqlx::query!(
+        r#"
+        SELECT
+            user_id IS NOT NULL OR consumed_at IS NOT NULL AS "used!",
+            expires_at,
+            link_user_id
+        FROM challenges
+        WHERE challenge = $1
+        FOR UPDATE
+        "#,
+        k1
+    )
+    .fetch_optional(&mut *trans)
+    .await?;
+
+    let link_user_id = challenge
+        .as_ref()
+        .and_then(|challenge| challenge.link_user_id);
+    check_challenge(
+        challenge.map(|challenge| ChallengeRow {
+            used: challenge.used,
+            expires_at: challenge.expires_at,
+        }),
+        Utc::now(),
+    )?;
+    Ok(link_user_id)
+}
+
+/// Binds a locked challenge to the account of `pubkey`, so that
+/// `/api/login/:k1` can issue its token.
+///
+/// A key seen for the first time gets an account of its own.
+async fn complete_login(
+    trans: &mut Transaction<'_, Postgres>,
+    k1: &[u8],
+    pubkey: &[u8],
+    key_type: KeyType,
+) -> Result<(), ApiError> {
+    let owner = sqlx::query_scalar!("SELECT user_id FROM user_keys WHERE pubkey = $1", pubkey)
+        .fetch_optional(&mut *trans)
+        .await?;
+
+    let user_id = match owner {
+        Some(owner) => owner,
+        None => {
+            let user_id = sqlx::query_scalar!("INSERT INTO users DEFAULT VALUES RETURNING id")
+                .fetch_one(&mut *trans)
+                .await?;
+            sqlx::query!(
+                "INSERT INTO user_keys (pubkey, key_type, user_id) VALUES ($1, $2, $3)",
+                pubkey,
+                key_type as KeyType,
+                user_id,
+            )
+            .execute(&mut *trans)
+            .await?;
+            user_id
+        }
+    };
+
+    sqlx::query!(
+        "UPDATE challenges SET user_id = $1 WHERE challenge = $2",
+        user_id,
+        k1,
+    )
+    .execute(&mut *trans)
+    .await?;
+
+    Ok(())
+}
+
+/// Adds `pubkey` to the account of `user_id` and consumes the locked challenge,
+/// which never yields a token.
+async fn link_key(
+    trans: &mut Transaction<'_, Postgres>,
+    k1: &[u8],
+    pubkey: &[u8],
+    key_type: KeyType,
+    user_id: i32,
+) -> Result<(), ApiError> {
+    // Merging two accounts is not supported.
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO user_keys (pubkey, key_type, user_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (pubkey) DO NOTHING
+        "#,
+        pubkey,
+        key_type as KeyType,
+        user_id,
+    )
+    .execute(&mut *trans)
+    .await?;
+
+    if inserted.rows_affected() == 0 {
+        return Err(LnurlError::KeyInUse.into());
+    }
+
+    sqlx::query!(
+        "UPDATE challenges SET consumed_at = NOW() WHERE challenge = $1",
+        k1,
+    )
+    .execute(&mut *trans)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes expired challenges until the process exits.
+pub async fn sweep(pool: PgPool) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        // A failed sweep is retried on the next tick.
+        if let Err(err) = sqlx::query!("DELETE FROM challenges WHERE expires_at <= NOW()")
+            .execute(&pool)
+            .await
+        {
+            tracing::error!("Failed to sweep challenges: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::SecretKey;
+
+    const K1: [u8; 32] = [7; 32];
+
+    fn sign(k1: &[u8], secret: u8) -> (String, String) {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[secret; 32]).unwrap();
+        let sig = secp.sign_ecdsa(&Message::from_slice(k1).unwrap(), &sk);
+        let pk = PublicKey::from_secret_key(&secp, &sk);
+        (
+            hex::encode(sig.serialize_der()),
+            hex::encode(pk.serialize()),
+        )
+    }
+
+    #[test]
+    fn should_accept_valid_signature() {
+        let (sig, key) = sign(&K1, 1);
+        let login = verify(&hex::encode(K1), &sig, &key).unwrap();
+        assert_eq!(K1.to_vec(), login.k1);
+        assert_eq!(hex::decode(key).unwrap(), login.pubkey);
+    }
+
+    #[test]
+    fn should_compress_uncompressed_key() {
+        let (sig, key) = sign(&K1, 1);
+        let pk = PublicKey::from_slice(&hex::decode(&key).unwrap()).unwrap();
+        let uncompressed = hex::encode(pk.serialize_uncompressed());
+
+        let login = verify(&hex::encode(K1), &sig, &uncompressed).unwrap();
+        assert_eq!(hex::decode(key).unwrap(), login.pubkey);
+    }
+
+    #[test]
+    fn should_reject_forged_signature() {
+        let (sig, key) = sign(&[8; 32], 1);
+        assert_eq!(
+            LnurlError::VerificationFailed,
+            verify(&hex::encode(K1), &sig, &key).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_reject_wrong_key() {
+        let (sig, _) = sign(&K1, 1);
+        let (_, other_key) = sign(&K1, 2);
+        assert_eq!(
+            LnurlError::VerificationFailed,
+            verify(&hex::encode(K1), &sig, &other_key).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_input() {
+        let (sig, key) = sign(&K1, 1);
+        let k1 = hex::encode(K1);
+
+        assert_eq!(LnurlError::InvalidK1, verify("zz", &sig, &key).unwrap_err());
+        assert_eq!(
+            LnurlError::InvalidK1,
+            verify("0707", &sig, &key).unwrap_err()
+        );
+        assert_eq!(
+            LnurlError::InvalidSignature,
+            verify(&k1, "zz", &key).unwrap_err()
+        );
+        assert_eq!(
+            LnurlError::InvalidSignature,
+            verify(&k1, "3006", &key).unwrap_err()
+        );
+        assert_eq!(LnurlError::InvalidKey, verify(&k1, &sig, "zz").unwrap_err());
+        assert_eq!(
+            LnurlError::InvalidKey,
+            verify(&k1, &sig, &format!("05{}", "02".repeat(32))).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_reject_replayed_challenge() {
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::minutes(5);
+
+        assert_eq!(
+            Ok(()),
+            check_challenge(
+                Some(ChallengeRow {
+                    used: false,
+                    expires_at
+                }),
+                now
+            )
+        );
+        assert_eq!(
+            Err(LnurlError::ChallengeAlreadyUsed),
+            check_challenge(
+                Some(ChallengeRow {
+                    used: true,
+                    expires_at
+                }),
+                now
+            )
+        );
+        assert_eq!(
+            Err(LnurlError::ChallengeNotFound),
+            check_challenge(None, now)
+        );
+    }
+
+    #[test]
+    fn should_reject_expired_challenge() {
+        let now = Utc::now();
+
+        assert_eq!(
+            Err(LnurlError::ChallengeExpired),
+            check_challenge(
+                Some(ChallengeRow {
+                    used: false,
+                    expires_at: now
+                }),
+                now
+            )
+        );
+    }
+}
@@ -1,20 +1,43 @@
 use axum::Server;
 use dotenv::dotenv;
+use scraper::vault::Keyring;
 use sqlx::PgPool;
-use statusmatch_poc::router;
+use statusmatch_poc::{events::Events, keys::Keys, lnurl, router, status_challenges};
 use std::{env, net::SocketAddr, path::PathBuf};
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    tracing_subscriber::fmt::init();
     let port = env::var("PORT").expect("PORT must be set").parse().unwrap();
     let service_url = env::var("SERVICE_URL").unwrap();
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = PgPool::connect(&db_url).await.unwrap();
     sqlx::migrate!().run(&pool).await.unwrap();
     let static_folder = PathBuf::from("public");
+    let evidence_dir =
+        PathBuf::from(env::var("EVIDENCE_DIR").unwrap_or_else(|_| "evidence".into()));
+    let keys = Keys::from_env().expect("JWT_KEYS must be set");
+    let keyring = Keyring::from_env().expect("CREDENTIAL_KEYS must be set");
 
-    let router = router(&service_url, pool, &static_folder);
+    let events = Events::new();
+    events
+        .clone()
+        .listen(&pool)
+        .await
+        .expect("Failed to listen for notifications");
+    tokio::spawn(lnurl::sweep(pool.clone()));
+    tokio::spawn(status_challenges::sweep(pool.clone()));
+
+    let router = router(
+        &service_url,
+        pool,
+        events,
+        &static_folder,
+        &evidence_dir,
+        keys,
+        keyring,
+    );
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     Server::bind(&addr)
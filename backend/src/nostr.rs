@@ -0,0 +1,316 @@
+use axum::{
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use secp256k1::{schnorr::Signature, Message, Secp256k1, XOnlyPublicKey};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::lnurl::LnurlError;
+
+/// NIP-98 HTTP Auth.
+const HTTP_AUTH_KIND: u32 = 27235;
+
+/// How far `created_at` may be from the server clock.
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+
+#[derive(Debug, PartialEq)]
+pub enum NostrError {
+    MissingHeader,
+    MalformedEvent,
+    WrongKind,
+    Stale,
+    UrlMismatch,
+    MethodMismatch,
+    MissingChallenge,
+    InvalidId,
+    InvalidKey,
+    InvalidSignature,
+    VerificationFailed,
+    Challenge(LnurlError),
+}
+
+impl From<LnurlError> for NostrError {
+    fn from(err: LnurlError) -> Self {
+        NostrError::Challenge(err)
+    }
+}
+
+impl IntoResponse for NostrError {
+    fn into_response(self) -> Response {
+        let error_message = match &self {
+            NostrError::MissingHeader => "Authorization must be a Nostr event",
+            NostrError::MalformedEvent => "Event is malformed",
+            NostrError::WrongKind => "Event must be of kind 27235",
+            NostrError::Stale => "Event is too old or in the future",
+            NostrError::UrlMismatch => "Event is for another URL",
+            NostrError::MethodMismatch => "Event is for another method",
+            NostrError::MissingChallenge => "Event must have a challenge tag",
+            NostrError::InvalidId => "Event id does not match its content",
+            NostrError::InvalidKey => "Event pubkey is invalid",
+            NostrError::InvalidSignature => "Event signature is invalid",
+            NostrError::VerificationFailed => "Signature does not match the pubkey",
+            NostrError::Challenge(err) => err.reason(),
+        };
+
+        let body = Json(json!({
+            "error": error_message,
+        }));
+
+        (StatusCode::UNAUTHORIZED, body).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct Event {
+    id: String,
+    pubkey: String,
+    created_at: i64,
+    kind: u32,
+    tags: Vec<Vec<String>>,
+    content: String,
+    sig: String,
+}
+
+impl Event {
+    fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.first().map(String::as_str) == Some(name))
+            .and_then(|tag| tag.get(1))
+            .map(String::as_str)
+    }
+
+    /// The event id as defined by NIP-01.
+    fn compute_id(&self) -> [u8; 32] {
+        let serialized = json!([
+            0,
+            self.pubkey,
+            self.created_at,
+            self.kind,
+            self.tags,
+            self.content
+        ]);
+        Sha256::digest(serialized.to_string().as_bytes()).into()
+    }
+}
+
+/// A Nostr login whose event has been verified against its challenge.
+#[derive(Debug)]
+pub struct VerifiedLogin {
+    pub k1: Vec<u8>,
+    /// The 32-byte x-only public key.
+    pub pubkey: Vec<u8>,
+}
+
+/// Reads the `Authorization: Nostr <base64 event>` header of a NIP-98 request.
+pub fn authorization(headers: &HeaderMap) -> Result<&str, NostrError> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Nostr "))
+        .ok_or(NostrError::MissingHeader)
+}
+
+/// Checks that the NIP-98 event was signed for `method` on `url` within the
+/// allowed clock skew, and carries the challenge in a `challenge` tag.
+pub fn verify(
+    authorization: &str,
+    url: &str,
+    method: &str,
+    now: i64,
+) -> Result<VerifiedLogin, NostrError> {
+    let event: Event = STANDARD
+        .decode(authorization.trim())
+        .ok()
+        .and_then(|event| serde_json::from_slice(&event).ok())
+        .ok_or(NostrError::MalformedEvent)?;
+
+    if event.kind != HTTP_AUTH_KIND {
+        return Err(NostrError::WrongKind);
+    }
+    if (now - event.created_at).abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(NostrError::Stale);
+    }
+    if event.tag("u") != Some(url) {
+        return Err(NostrError::UrlMismatch);
+    }
+    if !matches!(event.tag("method"), Some(m) if m.eq_ignore_ascii_case(method)) {
+        return Err(NostrError::MethodMismatch);
+    }
+    let k1 = event
+        .tag("challenge")
+        .and_then(|k1| hex::decode(k1).ok())
+        .filter(|k1| k1.len() == 32)
+        .ok_or(NostrError::MissingChallenge)?;
+
+    let id = event.compute_id();
+    if hex::decode(&event.id).ok().as_deref() != Some(&id[..]) {
+        return Err(NostrError::InvalidId);
+    }
+    let pk = hex::decode(&event.pubkey)
+        .ok()
+        .and_then(|pubkey| XOnlyPublicKey::from_slice(&pubkey).ok())
+        .ok_or(NostrError::InvalidKey)?;
+    let sig = hex::decode(&event.sig)
+        .ok()
+        .and_then(|sig| Signature::from_slice(&sig).ok())
+        .ok_or(NostrError::InvalidSignature)?;
+
+    Secp256k1::verification_only()
+        .verify_schnorr(&sig, &Message::from_slice(&id).unwrap(), &pk)
+        .map_err(|_| NostrError::VerificationFailed)?;
+
+    Ok(VerifiedLogin {
+        k1,
+        pubkey: pk.serialize().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::KeyPair;
+
+    const URL: &str = "http://localhost:8080/api/auth/nostr";
+    const NOW: i64 = 1_680_000_000;
+    const K1: [u8; 32] = [7; 32];
+
+    struct Unsigned {
+        created_at: i64,
+        kind: u32,
+        tags: Vec<Vec<String>>,
+    }
+
+    impl Default for Unsigned {
+        fn default() -> Self {
+            Self {
+                created_at: NOW,
+                kind: HTTP_AUTH_KIND,
+                tags: vec![
+                    vec!["u".to_string(), URL.to_string()],
+                    vec!["method".to_string(), "POST".to_string()],
+                    vec!["challenge".to_string(), hex::encode(K1)],
+                ],
+            }
+        }
+    }
+
+    fn keypair(secret: u8) -> KeyPair {
+        KeyPair::from_seckey_slice(&Secp256k1::new(), &[secret; 32]).unwrap()
+    }
+
+    fn sign(unsigned: Unsigned, keypair: &KeyPair) -> serde_json::Value {
+        let secp = Secp256k1::new();
+        let mut event = Event {
+            id: String::new(),
+            pubkey: hex::encode(keypair.x_only_public_key().0.serialize()),
+            created_at: unsigned.created_at,
+            kind: unsigned.kind,
+            tags: unsigned.tags,
+            content: String::new(),
+            sig: String::new(),
+        };
+        let id = event.compute_id();
+        let sig = secp.sign_schnorr_no_aux_rand(&Message::from_slice(&id).unwrap(), keypair);
+        event.id = hex::encode(id);
+        event.sig = hex::encode(sig.as_ref());
+
+        json!({
+            "id": event.id,
+            "pubkey": event.pubkey,
+            "created_at": event.created_at,
+            "kind": event.kind,
+            "tags": event.tags,
+            "content": event.content,
+            "sig": event.sig,
+        })
+    }
+
+    fn encode(event: &serde_json::Value) -> String {
+        STANDARD.encode(event.to_string())
+    }
+
+    #[test]
+    fn should_accept_valid_event() {
+        let keypair = keypair(1);
+        let event = sign(Unsigned::default(), &keypair);
+
+        let login = verify(&encode(&event), URL, "POST", NOW + 30).unwrap();
+        assert_eq!(K1.to_vec(), login.k1);
+        assert_eq!(
+            keypair.x_only_public_key().0.serialize().to_vec(),
+            login.pubkey
+        );
+    }
+
+    #[test]
+    fn should_reject_forged_event() {
+        let mut event = sign(Unsigned::default(), &keypair(1));
+        // Claim the event was signed by someone else.
+        event["pubkey"] = json!(hex::encode(keypair(2).x_only_public_key().0.serialize()));
+        assert_eq!(
+            NostrError::InvalidId,
+            verify(&encode(&event), URL, "POST", NOW).unwrap_err()
+        );
+
+        let mut event = sign(Unsigned::default(), &keypair(1));
+        event["sig"] = sign(Unsigned::default(), &keypair(2))["sig"].clone();
+        assert_eq!(
+            NostrError::VerificationFailed,
+            verify(&encode(&event), URL, "POST", NOW).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_reject_event_for_another_request() {
+        let event = encode(&sign(Unsigned::default(), &keypair(1)));
+
+        assert_eq!(
+            NostrError::UrlMismatch,
+            verify(&event, "http://evil.example/api/auth/nostr", "POST", NOW).unwrap_err()
+        );
+        assert_eq!(
+            NostrError::MethodMismatch,
+            verify(&event, URL, "GET", NOW).unwrap_err()
+        );
+        assert_eq!(
+            NostrError::Stale,
+            verify(&event, URL, "POST", NOW + 61).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_event() {
+        let without_challenge = Unsigned {
+            tags: Unsigned::default().tags[..2].to_vec(),
+            ..Default::default()
+        };
+        let wrong_kind = Unsigned {
+            kind: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            NostrError::MalformedEvent,
+            verify("not base64", URL, "POST", NOW).unwrap_err()
+        );
+        assert_eq!(
+            NostrError::MissingChallenge,
+            verify(
+                &encode(&sign(without_challenge, &keypair(1))),
+                URL,
+                "POST",
+                NOW
+            )
+            .unwrap_err()
+        );
+        assert_eq!(
+            NostrError::WrongKind,
+            verify(&encode(&sign(wrong_kind, &keypair(1))), URL, "POST", NOW).unwrap_err()
+        );
+    }
+}
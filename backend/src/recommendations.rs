@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use confidence::{Confidence, Tally, HALF_LIFE_DAYS, UNDATED_WEIGHT};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{auth::Claims, error::ApiError, LinkQuery, Program, Status};
+
+/// A status the user can match into, along with the status of theirs to present.
+#[derive(Serialize)]
+pub struct Recommendation {
+    program: Program,
+    status: Status,
+    from: UserStatus,
+    confidence: Confidence,
+}
+
+#[derive(Serialize)]
+struct UserStatus {
+    program: Program,
+    status: Status,
+}
+
+/// Keeps the highest status of every target program, and among the statuses
+/// of the user that reach it, the one most likely to succeed. The most likely
+/// recommendations come first.
+fn pick_best(candidates: Vec<Recommendation>) -> Vec<Recommendation> {
+    let mut best: HashMap<i32, Recommendation> = HashMap::new();
+    for candidate in candidates {
+        match best.get(&candidate.program.id) {
+            Some(current)
+                if (current.status.level, current.confidence.probability)
+                    >= (candidate.status.level, candidate.confidence.probability) => {}
+            _ => {
+                best.insert(candidate.program.id, candidate);
+            }
+        }
+    }
+
+    let mut recommendations: Vec<_> = best.into_values().collect();
+    recommendations.sort_by(|a, b| {
+        b.confidence
+            .probability
+            .total_cmp(&a.confidence.probability)
+            .then_with(|| a.program.name.cmp(&b.program.name))
+    });
+    recommendations
+}
+
+/// Runs `diagnose_links` from every current status of the user at once.
+///
+/// Programs in which the user already holds the recommended level or higher
+/// are left out.
+pub async fn get_recommendations(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Query(LinkQuery { months }): Query<LinkQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if matches!(months, Some(months) if months <= 0) {
+        return Err(ApiError::InvalidInput("months must be positive"));
+    }
+
+    let mut conn = pool.acquire().await?;
+
+    let rows = sqlx::query!(
+        r#"
+            WITH held AS (
+                SELECT program_id, level
+                FROM user_statuses
+                WHERE
+                    user_id = $1
+                    -- A lapsed declaration is no longer something to match from.
+                    AND (expires_on IS NULL OR expires_on >= CURRENT_DATE)
+            ),
+            tallies AS (
+                SELECT
+                    held.program_id AS from_program_id,
+                    held.level AS from_status_level,
+                    reports.to_program_id,
+                    reports.to_status_level,
+                    COUNT(*) FILTER (WHERE result = 'match') AS matches,
+                    COUNT(*) FILTER (WHERE result = 'deny') AS denies,
+                    COUNT(*) FILTER (WHERE result = 'challenge') AS challenges,
+                    SUM(weight) FILTER (WHERE result = 'match') AS weighted_matches,
+                    SUM(weight) FILTER (WHERE result = 'deny') AS weighted_denies,
+                    SUM(weight) FILTER (WHERE result = 'challenge') AS weighted_challenges
+                FROM held
+                INNER JOIN weighted_reports($2, $4, $3) AS reports
+                    ON reports.from_program_id = held.program_id
+                    AND reports.from_status_level <= held.level
+                GROUP BY
+                    held.program_id,
+                    held.level,
+                    reports.to_program_id,
+                    reports.to_status_level
+            )
+            SELECT
+                (to_programs.id, to_programs.name) AS "program!: Program",
+                (
+                    to_statuses.program_id,
+                    to_statuses.level,
+                    to_statuses.name
+                ) AS "status!: Status",
+                (from_programs.id, from_programs.name) AS "from_program!: Program",
+                (
+                    from_statuses.program_id,
+                    from_statuses.level,
+                    from_statuses.name
+                ) AS "from_status!: Status",
+                tallies.matches AS "matches!",
+                tallies.denies AS "denies!",
+                tallies.challenges AS "challenges!",
+                COALESCE(tallies.weighted_matches, 0) AS "weighted_matches!",
+                COALESCE(tallies.weighted_denies, 0) AS "weighted_denies!",
+                COALESCE(tallies.weighted_challenges, 0) AS "weighted_challenges!"
+            FROM tallies
+            INNER JOIN programs AS to_programs
+                ON tallies.to_program_id = to_programs.id
+            INNER JOIN program_statuses AS to_statuses
+                ON tallies.to_program_id = to_statuses.program_id
+                AND tallies.to_status_level = to_statuses.level
+            INNER JOIN programs AS from_programs
+                ON tallies.from_program_id = from_programs.id
+            INNER JOIN program_statuses AS from_statuses
+                ON tallies.from_program_id = from_statuses.program_id
+                AND tallies.from_status_level = from_statuses.level
+            WHERE
+                tallies.matches > 0
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM held
+                    WHERE
+                        held.program_id = tallies.to_program_id
+                        AND held.level >= tallies.to_status_level
+                )
+        "#,
+        user_id,
+        HALF_LIFE_DAYS,
+        months,
+        UNDATED_WEIGHT,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    let candidates = rows
+        .into_iter()
+        .map(|row| Recommendation {
+            program: row.program,
+            status: row.status,
+            from: UserStatus {
+                program: row.from_program,
+                status: row.from_status,
+            },
+            confidence: Tally {
+                matches: row.matches,
+                denies: row.denies,
+                challenges: row.challenges,
+                weighted_matches: row.weighted_matches,
+                weighted_denies: row.weighted_denies,
+                weighted_challenges: row.weighted_challenges,
+            }
+            .into(),
+        })
+        .collect();
+
+    Ok((StatusCode::OK, Json(pick_best(candidates))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(
+        to: (i32, &str),
+        level: i32,
+        from: (i32, &str),
+        probability: f64,
+    ) -> Recommendation {
+        let status = |program_id, level| Status {
+            program_id,
+            level,
+            name: format!("Level {}", level),
+        };
+        Recommendation {
+            program: Program {
+                id: to.0,
+                name: to.1.to_string(),
+            },
+            status: status(to.0, level),
+            from: UserStatus {
+                program: Program {
+                    id: from.0,
+                    name: from.1.to_string(),
+                },
+                status: status(from.0, 1),
+            },
+            confidence: Confidence {
+                matches: 1,
+                denies: 0,
+                challenges: 0,
+                probability,
+            },
+        }
+    }
+
+    #[test]
+    fn should_keep_highest_level_per_program() {
+        let best = pick_best(vec![
+            candidate((2, "Hilton Honors"), 2, (1, "Dormy Inn"), 0.9),
+            candidate((2, "Hilton Honors"), 3, (5, "Marriott Bonvoy"), 0.6),
+            candidate((4, "IHG One Rewards"), 1, (1, "Dormy Inn"), 0.7),
+        ]);
+
+        let picked: Vec<_> = best
+            .iter()
+            .map(|r| (r.program.id, r.status.level, r.from.program.id))
+            .collect();
+        assert_eq!(vec![(4, 1, 1), (2, 3, 5)], picked);
+    }
+
+    #[test]
+    fn should_present_most_likely_source_status() {
+        let best = pick_best(vec![
+            candidate((2, "Hilton Honors"), 2, (1, "Dormy Inn"), 0.6),
+            candidate((2, "Hilton Honors"), 2, (5, "Marriott Bonvoy"), 0.8),
+            candidate((2, "Hilton Honors"), 2, (4, "IHG One Rewards"), 0.7),
+        ]);
+
+        assert_eq!(1, best.len());
+        assert_eq!(5, best[0].from.program.id);
+    }
+}
@@ -0,0 +1,423 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+
+use crate::{auth::Claims, error::ApiError, status_challenges::ChallengeState, Program, Status};
+
+/// Reports a user may submit within a day, approved or not.
+pub const MAX_REPORTS_PER_DAY: i64 = 5;
+
+const MAX_NOTES_LENGTH: usize = 2000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "report_result", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReportResult {
+    Match,
+    Deny,
+    Challenge,
+}
+
+/// Only approved reports count towards links.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "report_state", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReportState {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug)]
+pub enum ReportError {
+    StatusNotFound,
+    SameProgram,
+    FutureDate,
+    NotesTooLong,
+    TooManyReports,
+}
+
+impl IntoResponse for ReportError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            ReportError::StatusNotFound => (StatusCode::NOT_FOUND, "Status is not found"),
+            ReportError::SameProgram => (
+                StatusCode::BAD_REQUEST,
+                "A match must be into another program",
+            ),
+            ReportError::FutureDate => (StatusCode::BAD_REQUEST, "date must not be in the future"),
+            ReportError::NotesTooLong => (
+                StatusCode::BAD_REQUEST,
+                "notes must be at most 2000 characters",
+            ),
+            ReportError::TooManyReports => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many reports, try again tomorrow",
+            ),
+        };
+
+        let body = Json(json!({
+            "error": error_message,
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewReport {
+    from_program_id: i32,
+    from_status_level: i32,
+    to_program_id: i32,
+    to_status_level: i32,
+    result: ReportResult,
+    /// When the match was attempted, today unless given.
+    date: Option<NaiveDate>,
+    notes: Option<String>,
+}
+
+/// Submits the outcome of a match the user attempted. It counts once a
+/// moderator approves it.
+pub async fn submit_report(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Json(report): Json<NewReport>,
+) -> Result<impl IntoResponse, ApiError> {
+    if report.from_program_id == report.to_program_id {
+        return Err(ReportError::SameProgram.into());
+    }
+    if matches!(report.date, Some(date) if date > Utc::now().date_naive()) {
+        return Err(ReportError::FutureDate.into());
+    }
+    let notes = report
+        .notes
+        .as_deref()
+        .map(str::trim)
+        .filter(|notes| !notes.is_empty());
+    if matches!(notes, Some(notes) if notes.chars().count() > MAX_NOTES_LENGTH) {
+        return Err(ReportError::NotesTooLong.into());
+    }
+
+    let mut trans = pool.begin().await?;
+
+    let statuses = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM program_statuses
+        WHERE
+            (program_id = $1 AND level = $2)
+            OR (program_id = $3 AND level = $4)
+        "#,
+        report.from_program_id,
+        report.from_status_level,
+        report.to_program_id,
+        report.to_status_level,
+    )
+    .fetch_one(&mut trans)
+    .await?;
+    if statuses != 2 {
+        return Err(ReportError::StatusNotFound.into());
+    }
+
+    // Locks the user so that concurrent submissions cannot exceed the limit.
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_one(&mut trans)
+        .await?;
+    let submitted = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM reports
+        WHERE
+            user_id = $1
+            AND submitted_at > NOW() - INTERVAL '1 day'
+        "#,
+        user_id,
+    )
+    .fetch_one(&mut trans)
+    .await?;
+    if submitted >= MAX_REPORTS_PER_DAY {
+        return Err(ReportError::TooManyReports.into());
+    }
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO reports (
+            from_program_id,
+            from_status_level,
+            to_program_id,
+            to_status_level,
+            result,
+            created_at,
+            notes,
+            state,
+            user_id
+        ) VALUES (
+            $1,
+            $2,
+            $3,
+            $4,
+            $5,
+            COALESCE($6::DATE::TIMESTAMP AT TIME ZONE 'UTC', NOW()),
+            $7,
+            'pending',
+            $8
+        )
+        RETURNING id
+        "#,
+        report.from_program_id,
+        report.from_status_level,
+        report.to_program_id,
+        report.to_status_level,
+        report.result as ReportResult,
+        report.date,
+        notes,
+        user_id,
+    )
+    .fetch_one(&mut trans)
+    .await?;
+
+    trans.commit().await?;
+
+    let resp = json!({
+        "id": id,
+        "state": ReportState::Pending,
+    });
+    Ok((StatusCode::CREATED, Json(resp)))
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    id: i32,
+    /// Absent for imported reports.
+    user_id: Option<i32>,
+    from_program: Program,
+    from_status: Status,
+    to_program: Program,
+    to_status: Status,
+    result: ReportResult,
+    /// Only for reports submitted when a status challenge finished.
+    challenge_outcome: Option<ChallengeState>,
+    /// Absent for imported reports whose date is unknown.
+    created_at: Option<DateTime<Utc>>,
+    notes: Option<String>,
+    state: ReportState,
+    submitted_at: DateTime<Utc>,
+}
+
+/// Reports of `user_id` in `state`, or of everyone and in any state when `None`,
+/// from the latest submission.
+pub async fn find_reports(
+    conn: &mut PgConnection,
+    user_id: Option<i32>,
+    state: Option<ReportState>,
+) -> sqlx::Result<Vec<Report>> {
+    sqlx::query_as!(
+        Report,
+        r#"
+        SELECT
+            reports.id,
+            reports.user_id,
+            (from_programs.id, from_programs.name) AS "from_program!: Program",
+            (
+                from_statuses.program_id,
+                from_statuses.level,
+                from_statuses.name
+            ) AS "from_status!: Status",
+            (to_programs.id, to_programs.name) AS "to_program!: Program",
+            (
+                to_statuses.program_id,
+                to_statuses.level,
+                to_statuses.name
+            ) AS "to_status!: Status",
+            reports.result AS "result: ReportResult",
+            reports.challenge_outcome AS "challenge_outcome: ChallengeState",
+            reports.created_at,
+            reports.notes,
+            reports.state AS "state: ReportState",
+            reports.submitted_at
+        FROM reports
+        INNER JOIN programs AS from_programs
+            ON reports.from_program_id = from_programs.id
+        INNER JOIN program_statuses AS from_statuses
+            ON reports.from_program_id = from_statuses.program_id
+            AND reports.from_status_level = from_statuses.level
+        INNER JOIN programs AS to_programs
+            ON reports.to_program_id = to_programs.id
+        INNER JOIN program_statuses AS to_statuses
+            ON reports.to_program_id = to_statuses.program_id
+            AND reports.to_status_level = to_statuses.level
+        WHERE
+            ($1::INT IS NULL OR reports.user_id = $1)
+            AND ($2::report_state IS NULL OR reports.state = $2)
+        ORDER BY reports.submitted_at DESC
+        "#,
+        user_id,
+        state as Option<ReportState>,
+    )
+    .fetch_all(conn)
+    .await
+}
+
+pub async fn list_reports(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let reports = find_reports(&mut conn, Some(user_id), None).await?;
+
+    Ok((StatusCode::OK, Json(reports)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::{Path, Query};
+
+    use super::*;
+    use crate::{
+        auth::Role,
+        testing::{add_program, add_user, claims, json_of, status_of},
+        LinkQuery,
+    };
+
+    struct Fixture {
+        user_id: i32,
+        hilton: i32,
+        marriott: i32,
+    }
+
+    async fn fixture(pool: &PgPool) -> Fixture {
+        Fixture {
+            user_id: add_user(pool, Role::Member).await,
+            hilton: add_program(pool, "Hilton Honors", &["Gold", "Diamond"]).await,
+            marriott: add_program(pool, "Marriott Bonvoy", &["Gold Elite", "Platinum Elite"]).await,
+        }
+    }
+
+    fn report(from_program_id: i32, to_program_id: i32) -> NewReport {
+        NewReport {
+            from_program_id,
+            from_status_level: 2,
+            to_program_id,
+            to_status_level: 2,
+            result: ReportResult::Match,
+            date: None,
+            notes: None,
+        }
+    }
+
+    async fn submit(pool: &PgPool, user_id: i32, report: NewReport) -> StatusCode {
+        status_of(
+            submit_report(
+                claims(user_id, Role::Member),
+                State(pool.clone()),
+                Json(report),
+            )
+            .await,
+        )
+    }
+
+    #[sqlx::test]
+    async fn should_reject_invalid_report(pool: PgPool) {
+        let Fixture {
+            user_id,
+            hilton,
+            marriott,
+        } = fixture(&pool).await;
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            submit(&pool, user_id, report(hilton, hilton)).await
+        );
+
+        let tomorrow = Utc::now().date_naive().succ_opt();
+        let future = NewReport {
+            date: tomorrow,
+            ..report(hilton, marriott)
+        };
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            submit(&pool, user_id, future).await
+        );
+
+        let unknown = NewReport {
+            to_status_level: 3,
+            ..report(hilton, marriott)
+        };
+        assert_eq!(StatusCode::NOT_FOUND, submit(&pool, user_id, unknown).await);
+
+        let notes = NewReport {
+            notes: Some("a".repeat(MAX_NOTES_LENGTH + 1)),
+            ..report(hilton, marriott)
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, submit(&pool, user_id, notes).await);
+    }
+
+    #[sqlx::test]
+    async fn should_limit_reports_per_day(pool: PgPool) {
+        let Fixture {
+            user_id,
+            hilton,
+            marriott,
+        } = fixture(&pool).await;
+
+        for _ in 0..MAX_REPORTS_PER_DAY {
+            assert_eq!(
+                StatusCode::CREATED,
+                submit(&pool, user_id, report(hilton, marriott)).await
+            );
+        }
+        assert_eq!(
+            StatusCode::TOO_MANY_REQUESTS,
+            submit(&pool, user_id, report(hilton, marriott)).await
+        );
+
+        // Others are not limited by the user.
+        let other = add_user(&pool, Role::Member).await;
+        assert_eq!(
+            StatusCode::CREATED,
+            submit(&pool, other, report(hilton, marriott)).await
+        );
+    }
+
+    #[sqlx::test]
+    async fn should_count_only_approved_reports_towards_links(pool: PgPool) {
+        let Fixture {
+            user_id,
+            hilton,
+            marriott,
+        } = fixture(&pool).await;
+        assert_eq!(
+            StatusCode::CREATED,
+            submit(&pool, user_id, report(hilton, marriott)).await
+        );
+
+        let links = || async {
+            let links = crate::diagnose_links(
+                State(pool.clone()),
+                Path((hilton, 2)),
+                Query(LinkQuery { months: None }),
+            )
+            .await
+            .unwrap()
+            .into_response();
+            json_of(links).await
+        };
+        assert_eq!(json!([]), links().await);
+
+        sqlx::query("UPDATE reports SET state = 'approved'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let links = links().await;
+        assert_eq!(json!("Platinum Elite"), links[0]["status"]);
+        assert_eq!(json!(1), links[0]["confidence"]["matches"]);
+    }
+}
@@ -0,0 +1,222 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::{
+    auth::{self, AuthError, Claims},
+    error::ApiError,
+    keys::Keys,
+};
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Serialize)]
+struct Session {
+    id: i32,
+    user_agent: Option<String>,
+    created_at: DateTime<Utc>,
+    refreshed_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    /// Whether the session is the one making the request.
+    current: bool,
+}
+
+pub async fn refresh_token(
+    State(pool): State<PgPool>,
+    State(keys): State<Arc<Keys>>,
+    Json(RefreshRequest { refresh_token }): Json<RefreshRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+    auth::refresh(&mut conn, &keys, &refresh_token).await
+}
+
+pub async fn logout(
+    Claims { jti, .. }: Claims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    sqlx::query!(
+        "UPDATE sessions SET revoked_at = NOW() WHERE access_token_id = $1",
+        &jti,
+    )
+    .execute(&mut conn)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_sessions(
+    Claims { user_id, jti, .. }: Claims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let sessions = sqlx::query_as!(
+        Session,
+        r#"
+        SELECT
+            id,
+            user_agent,
+            created_at,
+            refreshed_at,
+            expires_at,
+            access_token_id = $2 AS "current!"
+        FROM sessions
+        WHERE
+            user_id = $1
+            AND revoked_at IS NULL
+            AND expires_at > NOW()
+        ORDER BY refreshed_at DESC
+        "#,
+        user_id,
+        &jti,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    Ok((StatusCode::OK, Json(sessions)))
+}
+
+pub async fn revoke_session(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE
+            id = $1
+            AND user_id = $2
+            AND revoked_at IS NULL
+        "#,
+        id,
+        user_id,
+    )
+    .execute(&mut conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AuthError::SessionNotFound.into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::Role,
+        testing::{add_user, claims, keys, login, status_of},
+    };
+
+    async fn refresh(pool: &PgPool, token: &str) -> Result<String, ApiError> {
+        let mut conn = pool.acquire().await?;
+        let auth = auth::refresh(&mut conn, &keys(), token).await?;
+        let auth = serde_json::to_value(auth).unwrap();
+        Ok(auth["refresh_token"].as_str().unwrap().to_string())
+    }
+
+    async fn session_claims(pool: &PgPool, user_id: i32) -> Claims {
+        let jti = sqlx::query_scalar(
+            "SELECT access_token_id FROM sessions WHERE user_id = $1 ORDER BY id DESC LIMIT 1",
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        Claims {
+            jti,
+            ..claims(user_id, Role::Member)
+        }
+    }
+
+    async fn session_id(pool: &PgPool, user_id: i32) -> i32 {
+        sqlx::query_scalar("SELECT id FROM sessions WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn should_rotate_refresh_token(pool: PgPool) {
+        let user_id = add_user(&pool, Role::Member).await;
+        let (_, first) = login(&pool, user_id).await;
+
+        let second = refresh(&pool, &first).await.unwrap();
+        let third = refresh(&pool, &second).await.unwrap();
+        assert_ne!(second, third);
+    }
+
+    #[sqlx::test]
+    async fn should_revoke_session_when_refresh_token_is_reused(pool: PgPool) {
+        let user_id = add_user(&pool, Role::Member).await;
+        let (_, first) = login(&pool, user_id).await;
+        let second = refresh(&pool, &first).await.unwrap();
+
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status_of(refresh(&pool, &first).await)
+        );
+        // The legitimate holder is logged out along with whoever reused it.
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status_of(refresh(&pool, &second).await)
+        );
+    }
+
+    #[sqlx::test]
+    async fn should_not_refresh_after_logout(pool: PgPool) {
+        let user_id = add_user(&pool, Role::Member).await;
+        let (_, refresh_token) = login(&pool, user_id).await;
+
+        let claims = session_claims(&pool, user_id).await;
+        assert_eq!(
+            StatusCode::NO_CONTENT,
+            status_of(logout(claims, State(pool.clone())).await)
+        );
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status_of(refresh(&pool, &refresh_token).await)
+        );
+    }
+
+    #[sqlx::test]
+    async fn should_only_revoke_own_session(pool: PgPool) {
+        let user_id = add_user(&pool, Role::Member).await;
+        let other = add_user(&pool, Role::Member).await;
+        let (_, refresh_token) = login(&pool, user_id).await;
+        let id = session_id(&pool, user_id).await;
+
+        let revoke = |user_id| {
+            let pool = pool.clone();
+            async move {
+                status_of(
+                    revoke_session(claims(user_id, Role::Member), State(pool), Path(id)).await,
+                )
+            }
+        };
+        assert_eq!(StatusCode::NOT_FOUND, revoke(other).await);
+        assert_eq!(StatusCode::NO_CONTENT, revoke(user_id).await);
+        assert_eq!(StatusCode::NOT_FOUND, revoke(user_id).await);
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            status_of(refresh(&pool, &refresh_token).await)
+        );
+    }
+}
@@ -0,0 +1,549 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{auth::Claims, error::ApiError, Program, Status};
+
+/// Challenges past their deadline fail within this long.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "challenge_state", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeState {
+    Ongoing,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug)]
+pub enum ChallengeError {
+    NotFound,
+    StatusNotFound,
+    SameProgram,
+    NoRequirement,
+    PastDeadline,
+    Finished,
+}
+
+impl IntoResponse for ChallengeError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            ChallengeError::NotFound => (StatusCode::NOT_FOUND, "Challenge is not found"),
+            ChallengeError::StatusNotFound => (StatusCode::NOT_FOUND, "Status is not found"),
+            ChallengeError::SameProgram => (
+                StatusCode::BAD_REQUEST,
+                "A challenge must be into another program",
+            ),
+            ChallengeError::NoRequirement => (
+                StatusCode::BAD_REQUEST,
+                "required_nights or required_points must be positive",
+            ),
+            ChallengeError::PastDeadline => {
+                (StatusCode::BAD_REQUEST, "deadline must not be in the past")
+            }
+            ChallengeError::Finished => (StatusCode::CONFLICT, "Challenge is already finished"),
+        };
+
+        let body = Json(json!({
+            "error": error_message,
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+/// What is left of a requirement, or `None` when the challenge does not have it.
+fn remaining(required: Option<i32>, progress: i32) -> Option<i32> {
+    required.map(|required| (required - progress).max(0))
+}
+
+/// A challenge with both requirements needs both to be met.
+fn is_met(
+    required_nights: Option<i32>,
+    nights: i32,
+    required_points: Option<i32>,
+    points: i32,
+) -> bool {
+    remaining(required_nights, nights).unwrap_or(0) == 0
+        && remaining(required_points, points).unwrap_or(0) == 0
+}
+
+fn validate_requirement(required: Option<i32>) -> Result<Option<i32>, ChallengeError> {
+    match required {
+        Some(required) if required <= 0 => Err(ChallengeError::NoRequirement),
+        required => Ok(required),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewChallenge {
+    /// The status the challenge was offered for.
+    from_program_id: i32,
+    from_status_level: i32,
+    to_program_id: i32,
+    to_status_level: i32,
+    required_nights: Option<i32>,
+    required_points: Option<i32>,
+    deadline: NaiveDate,
+}
+
+pub async fn add_challenge(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Json(challenge): Json<NewChallenge>,
+) -> Result<impl IntoResponse, ApiError> {
+    if challenge.from_program_id == challenge.to_program_id {
+        return Err(ChallengeError::SameProgram.into());
+    }
+    let required_nights = validate_requirement(challenge.required_nights)?;
+    let required_points = validate_requirement(challenge.required_points)?;
+    if required_nights.is_none() && required_points.is_none() {
+        return Err(ChallengeError::NoRequirement.into());
+    }
+    if challenge.deadline < Utc::now().date_naive() {
+        return Err(ChallengeError::PastDeadline.into());
+    }
+
+    let mut conn = pool.acquire().await?;
+
+    let statuses = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM program_statuses
+        WHERE
+            (program_id = $1 AND level = $2)
+            OR (program_id = $3 AND level = $4)
+        "#,
+        challenge.from_program_id,
+        challenge.from_status_level,
+        challenge.to_program_id,
+        challenge.to_status_level,
+    )
+    .fetch_one(&mut conn)
+    .await?;
+    if statuses != 2 {
+        return Err(ChallengeError::StatusNotFound.into());
+    }
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO status_challenges (
+            user_id,
+            from_program_id,
+            from_status_level,
+            to_program_id,
+            to_status_level,
+            required_nights,
+            required_points,
+            deadline
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id
+        "#,
+        user_id,
+        challenge.from_program_id,
+        challenge.from_status_level,
+        challenge.to_program_id,
+        challenge.to_status_level,
+        required_nights,
+        required_points,
+        challenge.deadline,
+    )
+    .fetch_one(&mut conn)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(json!({ "id": id }))))
+}
+
+struct ChallengeRow {
+    id: i32,
+    from_program: Program,
+    from_status: Status,
+    to_program: Program,
+    to_status: Status,
+    required_nights: Option<i32>,
+    required_points: Option<i32>,
+    nights: i32,
+    points: i32,
+    deadline: NaiveDate,
+    days_left: i32,
+    state: ChallengeState,
+    report_id: Option<i32>,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct Requirement {
+    required: i32,
+    progress: i32,
+    remaining: i32,
+}
+
+impl Requirement {
+    fn new(required: Option<i32>, progress: i32) -> Option<Self> {
+        Some(Self {
+            required: required?,
+            progress,
+            remaining: remaining(required, progress)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Challenge {
+    id: i32,
+    from_program: Program,
+    from_status: Status,
+    to_program: Program,
+    to_status: Status,
+    /// Absent when the challenge does not count nights.
+    nights: Option<Requirement>,
+    /// Absent when the challenge does not count points.
+    points: Option<Requirement>,
+    deadline: NaiveDate,
+    /// Only while the challenge is ongoing; 0 on the day of the deadline.
+    days_left: Option<i32>,
+    state: ChallengeState,
+    /// The report submitted once the challenge finished.
+    report_id: Option<i32>,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<ChallengeRow> for Challenge {
+    fn from(row: ChallengeRow) -> Self {
+        Self {
+            id: row.id,
+            from_program: row.from_program,
+            from_status: row.from_status,
+            to_program: row.to_program,
+            to_status: row.to_status,
+            nights: Requirement::new(row.required_nights, row.nights),
+            points: Requirement::new(row.required_points, row.points),
+            deadline: row.deadline,
+            days_left: (row.state == ChallengeState::Ongoing).then_some(row.days_left.max(0)),
+            state: row.state,
+            report_id: row.report_id,
+            created_at: row.created_at,
+            finished_at: row.finished_at,
+        }
+    }
+}
+
+/// Ongoing challenges first, the closest deadline first.
+pub async fn list_challenges(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let rows = sqlx::query_as!(
+        ChallengeRow,
+        r#"
+        SELECT
+            status_challenges.id,
+            (from_programs.id, from_programs.name) AS "from_program!: Program",
+            (
+                from_statuses.program_id,
+                from_statuses.level,
+                from_statuses.name
+            ) AS "from_status!: Status",
+            (to_programs.id, to_programs.name) AS "to_program!: Program",
+            (
+                to_statuses.program_id,
+                to_statuses.level,
+                to_statuses.name
+            ) AS "to_status!: Status",
+            status_challenges.required_nights,
+            status_challenges.required_points,
+            status_challenges.nights,
+            status_challenges.points,
+            status_challenges.deadline,
+            status_challenges.deadline - CURRENT_DATE AS "days_left!",
+            status_challenges.state AS "state: ChallengeState",
+            status_challenges.report_id,
+            status_challenges.created_at,
+            status_challenges.finished_at
+        FROM status_challenges
+        INNER JOIN programs AS from_programs
+            ON status_challenges.from_program_id = from_programs.id
+        INNER JOIN program_statuses AS from_statuses
+            ON status_challenges.from_program_id = from_statuses.program_id
+            AND status_challenges.from_status_level = from_statuses.level
+        INNER JOIN programs AS to_programs
+            ON status_challenges.to_program_id = to_programs.id
+        INNER JOIN program_statuses AS to_statuses
+            ON status_challenges.to_program_id = to_statuses.program_id
+            AND status_challenges.to_status_level = to_statuses.level
+        WHERE status_challenges.user_id = $1
+        ORDER BY
+            status_challenges.state <> 'ongoing',
+            status_challenges.deadline
+        "#,
+        user_id,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    let challenges: Vec<Challenge> = rows.into_iter().map(Challenge::from).collect();
+
+    Ok((StatusCode::OK, Json(challenges)))
+}
+
+#[derive(Deserialize)]
+pub struct Progress {
+    /// Totals so far rather than increments, so that retries are harmless.
+    nights: Option<i32>,
+    points: Option<i32>,
+}
+
+/// Records progress by hand until the deadline. The challenge completes once
+/// every requirement is met, or once the status of the user reaches the target
+/// level, whether the scraper read it or the user declared it.
+pub async fn update_progress(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Path(id): Path<i32>,
+    Json(Progress { nights, points }): Json<Progress>,
+) -> Result<impl IntoResponse, ApiError> {
+    if matches!(nights, Some(nights) if nights < 0) || matches!(points, Some(points) if points < 0)
+    {
+        return Err(ApiError::InvalidInput(
+            "nights and points must not be negative",
+        ));
+    }
+
+    let mut trans = pool.begin().await?;
+
+    let challenge = sqlx::query!(
+        r#"
+        SELECT
+            required_nights,
+            required_points,
+            nights,
+            points,
+            state AS "state: ChallengeState",
+            deadline < CURRENT_DATE AS "past_deadline!"
+        FROM status_challenges
+        WHERE id = $1 AND user_id = $2
+        FOR UPDATE
+        "#,
+        id,
+        user_id,
+    )
+    .fetch_optional(&mut trans)
+    .await?
+    .ok_or(ChallengeError::NotFound)?;
+    if challenge.state != ChallengeState::Ongoing {
+        return Err(ChallengeError::Finished.into());
+    }
+    // Fails it now rather than waiting for the sweep, which would otherwise
+    // let late progress complete it.
+    if challenge.past_deadline {
+        sqlx::query!("SELECT finish_status_challenge($1, 'failed')", id)
+            .execute(&mut trans)
+            .await?;
+        trans.commit().await?;
+        return Err(ChallengeError::Finished.into());
+    }
+
+    let nights = nights.unwrap_or(challenge.nights);
+    let points = points.unwrap_or(challenge.points);
+
+    sqlx::query!(
+        "UPDATE status_challenges SET nights = $2, points = $3 WHERE id = $1",
+        id,
+        nights,
+        points,
+    )
+    .execute(&mut trans)
+    .await?;
+
+    if is_met(
+        challenge.required_nights,
+        nights,
+        challenge.required_points,
+        points,
+    ) {
+        sqlx::query!("SELECT finish_status_challenge($1, 'completed')", id)
+            .execute(&mut trans)
+            .await?;
+    }
+
+    trans.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fails challenges past their deadline until the process exits.
+pub async fn sweep(pool: PgPool) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        // A failed sweep is retried on the next tick.
+        if let Err(err) = sqlx::query!(
+            r#"
+            SELECT finish_status_challenge(id, 'failed')
+            FROM status_challenges
+            WHERE state = 'ongoing' AND deadline < CURRENT_DATE
+            "#
+        )
+        .execute(&pool)
+        .await
+        {
+            tracing::error!("Failed to sweep status challenges: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::Role,
+        testing::{add_program, add_user, claims},
+    };
+
+    #[test]
+    fn should_count_down_remaining_requirement() {
+        assert_eq!(Some(7), remaining(Some(10), 3));
+        assert_eq!(Some(0), remaining(Some(10), 12));
+        assert_eq!(None, remaining(None, 3));
+    }
+
+    #[test]
+    fn should_need_every_requirement() {
+        assert!(is_met(Some(10), 10, None, 0));
+        assert!(!is_met(Some(10), 10, Some(5000), 4000));
+        assert!(is_met(Some(10), 11, Some(5000), 5000));
+        assert!(!is_met(None, 0, Some(5000), 0));
+    }
+
+    async fn challenge(pool: &PgPool, user_id: i32) -> i32 {
+        let hilton = add_program(pool, "Hilton Honors", &["Gold", "Diamond"]).await;
+        let marriott =
+            add_program(pool, "Marriott Bonvoy", &["Gold Elite", "Platinum Elite"]).await;
+        sqlx::query_scalar(
+            r#"
+            INSERT INTO status_challenges (
+                user_id, from_program_id, from_status_level, to_program_id, to_status_level,
+                required_nights, deadline
+            ) VALUES ($1, $2, 2, $3, 2, 10, CURRENT_DATE)
+            RETURNING id
+            "#,
+        )
+        .bind(user_id)
+        .bind(hilton)
+        .bind(marriott)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn outcome(pool: &PgPool, id: i32) -> Option<ChallengeState> {
+        sqlx::query_scalar(
+            r#"
+            SELECT reports.challenge_outcome
+            FROM status_challenges
+            INNER JOIN reports ON status_challenges.report_id = reports.id
+            WHERE status_challenges.id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn should_report_completed_challenge(pool: PgPool) {
+        let user_id = add_user(&pool, Role::Member).await;
+        let id = challenge(&pool, user_id).await;
+
+        let progress = |nights| {
+            Json(Progress {
+                nights: Some(nights),
+                points: None,
+            })
+        };
+        update_progress(
+            claims(user_id, Role::Member),
+            State(pool.clone()),
+            Path(id),
+            progress(9),
+        )
+        .await
+        .unwrap();
+        update_progress(
+            claims(user_id, Role::Member),
+            State(pool.clone()),
+            Path(id),
+            progress(10),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(Some(ChallengeState::Completed), outcome(&pool, id).await);
+        assert!(matches!(
+            update_progress(
+                claims(user_id, Role::Member),
+                State(pool.clone()),
+                Path(id),
+                progress(11)
+            )
+            .await,
+            Err(ApiError::Challenge(ChallengeError::Finished))
+        ));
+    }
+
+    #[sqlx::test]
+    async fn should_fail_challenge_past_deadline(pool: PgPool) {
+        let user_id = add_user(&pool, Role::Member).await;
+        let id = challenge(&pool, user_id).await;
+        sqlx::query("UPDATE status_challenges SET deadline = CURRENT_DATE - 1 WHERE id = $1")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let progress = Json(Progress {
+            nights: Some(10),
+            points: None,
+        });
+        assert!(matches!(
+            update_progress(
+                claims(user_id, Role::Member),
+                State(pool.clone()),
+                Path(id),
+                progress
+            )
+            .await,
+            Err(ApiError::Challenge(ChallengeError::Finished))
+        ));
+        assert_eq!(Some(ChallengeState::Failed), outcome(&pool, id).await);
+    }
+
+    #[sqlx::test]
+    async fn should_report_failed_challenge(pool: PgPool) {
+        let user_id = add_user(&pool, Role::Member).await;
+        let id = challenge(&pool, user_id).await;
+
+        sqlx::query("SELECT finish_status_challenge($1, 'failed')")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(Some(ChallengeState::Failed), outcome(&pool, id).await);
+    }
+}
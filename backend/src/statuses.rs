@@ -0,0 +1,551 @@
+use std::path::PathBuf;
+
+use axum::{
+    body::Bytes,
+    extract::{
+        multipart::{Field, MultipartError},
+        Multipart, Path, State,
+    },
+    http::{
+        header::{CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+        StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::NaiveDate;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{auth::Claims, ensure_program, error::ApiError, StatusSource};
+
+pub type EvidenceDir = PathBuf;
+
+pub const MAX_EVIDENCE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Evidence is served back as uploaded, so only types that browsers display
+/// without running anything, along with the extension they are stored under.
+const EVIDENCE_TYPES: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("application/pdf", "pdf"),
+];
+
+#[derive(Debug)]
+pub enum StatusError {
+    UnknownLevel,
+    AlreadyVerified,
+    EvidenceNotFound,
+    EvidenceTooLarge,
+    UnsupportedEvidence,
+}
+
+impl IntoResponse for StatusError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            StatusError::UnknownLevel => (
+                StatusCode::BAD_REQUEST,
+                "Level is not a status of the program",
+            ),
+            StatusError::AlreadyVerified => (
+                StatusCode::CONFLICT,
+                "Status is verified through a linked credential",
+            ),
+            StatusError::EvidenceNotFound => (StatusCode::NOT_FOUND, "Evidence is not found"),
+            StatusError::EvidenceTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Evidence must be at most 5 MiB",
+            ),
+            StatusError::UnsupportedEvidence => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Evidence must be a PNG, JPEG or PDF",
+            ),
+        };
+
+        let body = Json(json!({
+            "error": error_message,
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+struct Evidence {
+    content_type: &'static str,
+    extension: &'static str,
+    bytes: Bytes,
+}
+
+/// The `multipart/form-data` body of a declaration.
+struct Declaration {
+    level: i32,
+    expires_on: Option<NaiveDate>,
+    evidence: Option<Evidence>,
+}
+
+/// The body limit may be hit while reading any field, not only the evidence.
+fn multipart_error(err: MultipartError) -> ApiError {
+    if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        StatusError::EvidenceTooLarge.into()
+    } else {
+        ApiError::InvalidInput("Body must be multipart/form-data")
+    }
+}
+
+async fn text(field: Field<'_>) -> Result<String, ApiError> {
+    field.text().await.map_err(multipart_error)
+}
+
+async fn read_declaration(mut multipart: Multipart) -> Result<Declaration, ApiError> {
+    let (mut level, mut expires_on, mut evidence) = (None, None, None);
+
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("level") => {
+                let value = text(field).await?;
+                let value = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| ApiError::InvalidInput("level must be a number"))?;
+                level = Some(value);
+            }
+            Some("expires_on") => {
+                let value = text(field).await?;
+                // Browsers send empty date inputs as empty fields.
+                if !value.trim().is_empty() {
+                    let value = NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+                        .map_err(|_| ApiError::InvalidInput("expires_on must be YYYY-MM-DD"))?;
+                    expires_on = Some(value);
+                }
+            }
+            Some("evidence") => {
+                let (content_type, extension) = EVIDENCE_TYPES
+                    .iter()
+                    .copied()
+                    .find(|(content_type, _)| field.content_type() == Some(content_type))
+                    .ok_or(StatusError::UnsupportedEvidence)?;
+                let bytes = field.bytes().await.map_err(multipart_error)?;
+                if bytes.len() > MAX_EVIDENCE_BYTES {
+                    return Err(StatusError::EvidenceTooLarge.into());
+                }
+                evidence = Some(Evidence {
+                    content_type,
+                    extension,
+                    bytes,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Declaration {
+        level: level.ok_or(ApiError::InvalidInput("level is required"))?,
+        expires_on,
+        evidence,
+    })
+}
+
+/// Removes evidence that no declaration refers to anymore.
+async fn remove_evidence(evidence_dir: &std::path::Path, file: &str) {
+    if let Err(err) = tokio::fs::remove_file(evidence_dir.join(file)).await {
+        tracing::warn!("Failed to remove evidence {}: {}", file, err);
+    }
+}
+
+/// Declares the level of the user in a program, from a `multipart/form-data`
+/// body with `level`, and optionally `expires_on` and an `evidence` file.
+///
+/// Meant for programs that have no scraper, but open to every program unless
+/// the status is verified through a linked credential. Replaces the previous
+/// declaration along with its evidence.
+pub async fn declare_status(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    State(evidence_dir): State<EvidenceDir>,
+    Path(program_id): Path<i32>,
+    multipart: Multipart,
+) -> Result<impl IntoResponse, ApiError> {
+    let Declaration {
+        level,
+        expires_on,
+        evidence,
+    } = read_declaration(multipart).await?;
+
+    let mut trans = pool.begin().await?;
+    ensure_program(&mut trans, program_id).await?;
+
+    let known_level = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM program_statuses WHERE program_id = $1 AND level = $2
+        ) AS "exists!"
+        "#,
+        program_id,
+        level,
+    )
+    .fetch_one(&mut trans)
+    .await?;
+    if !known_level {
+        return Err(StatusError::UnknownLevel.into());
+    }
+
+    let current = sqlx::query!(
+        r#"
+        SELECT
+            user_statuses.source AS "source: StatusSource",
+            user_statuses.evidence_file,
+            user_credentials.user_id IS NOT NULL AS "linked!"
+        FROM user_statuses
+        LEFT JOIN user_credentials
+            ON user_statuses.user_id = user_credentials.user_id
+            AND user_statuses.program_id = user_credentials.program_id
+        WHERE
+            user_statuses.user_id = $1
+            AND user_statuses.program_id = $2
+        FOR UPDATE OF user_statuses
+        "#,
+        user_id,
+        program_id,
+    )
+    .fetch_optional(&mut trans)
+    .await?;
+
+    // The scraper would overwrite the declaration on its next run anyway.
+    if matches!(&current, Some(c) if c.linked && matches!(c.source, StatusSource::Verified)) {
+        return Err(StatusError::AlreadyVerified.into());
+    }
+
+    let evidence_file = match &evidence {
+        Some(evidence) => {
+            let file = format!(
+                "{}.{}",
+                hex::encode(rand::random::<[u8; 16]>()),
+                evidence.extension
+            );
+            let write = async {
+                tokio::fs::create_dir_all(&evidence_dir).await?;
+                tokio::fs::write(evidence_dir.join(&file), &evidence.bytes).await
+            };
+            write.await.map_err(|err| {
+                tracing::error!("Failed to store evidence: {}", err);
+                ApiError::Internal
+            })?;
+            Some(file)
+        }
+        None => None,
+    };
+
+    let stored = async {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_statuses (
+                user_id, program_id, level, source,
+                expires_on, evidence_file, evidence_content_type
+            )
+            VALUES ($1, $2, $3, 'manual', $4, $5, $6)
+            ON CONFLICT (user_id, program_id)
+            DO UPDATE
+                SET
+                    level = $3,
+                    source = 'manual',
+                    expires_on = $4,
+                    evidence_file = $5,
+                    evidence_content_type = $6,
+                    updated_at = NOW()
+            "#,
+            user_id,
+            program_id,
+            level,
+            expires_on,
+            evidence_file,
+            evidence.as_ref().map(|evidence| evidence.content_type),
+        )
+        .execute(&mut trans)
+        .await?;
+
+        trans.commit().await
+    }
+    .await;
+    if let Err(err) = stored {
+        // The declaration does not refer to the evidence just written.
+        if let Some(file) = &evidence_file {
+            remove_evidence(&evidence_dir, file).await;
+        }
+        return Err(err.into());
+    }
+
+    if let Some(previous) = current.and_then(|c| c.evidence_file) {
+        remove_evidence(&evidence_dir, &previous).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_evidence(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    State(evidence_dir): State<EvidenceDir>,
+    Path(program_id): Path<i32>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let evidence = sqlx::query!(
+        r#"
+        SELECT
+            evidence_file AS "file!",
+            evidence_content_type AS "content_type!"
+        FROM user_statuses
+        WHERE
+            user_id = $1
+            AND program_id = $2
+            AND evidence_file IS NOT NULL
+        "#,
+        user_id,
+        program_id,
+    )
+    .fetch_optional(&mut conn)
+    .await?
+    .ok_or(StatusError::EvidenceNotFound)?;
+
+    let bytes = tokio::fs::read(evidence_dir.join(&evidence.file))
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to read evidence {}: {}", evidence.file, err);
+            ApiError::Internal
+        })?;
+
+    let headers = [
+        (CONTENT_TYPE, evidence.content_type),
+        (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+    Ok((StatusCode::OK, headers, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::FromRequest, http::Request};
+
+    use super::*;
+    use crate::{
+        auth::Role,
+        testing::{add_program, add_user, claims, json_of, status_of},
+    };
+
+    const BOUNDARY: &str = "declaration-boundary";
+
+    /// A field is a name, the content type of a file if it is one, and a value.
+    async fn multipart(fields: &[(&str, Option<&str>, &[u8])]) -> Multipart {
+        let mut body = vec![];
+        for (name, content_type, value) in fields {
+            body.extend(format!("--{}\r\n", BOUNDARY).bytes());
+            match content_type {
+                Some(content_type) => body.extend(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"card\"\r\nContent-Type: {}\r\n\r\n",
+                        name, content_type
+                    )
+                    .bytes(),
+                ),
+                None => body.extend(
+                    format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).bytes(),
+                ),
+            }
+            body.extend(*value);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", BOUNDARY).bytes());
+
+        let request = Request::builder()
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    fn evidence_dir() -> EvidenceDir {
+        std::env::temp_dir().join(hex::encode(rand::random::<[u8; 8]>()))
+    }
+
+    #[tokio::test]
+    async fn should_read_declaration() {
+        let declaration = read_declaration(
+            multipart(&[
+                ("level", None, b" 2 "),
+                ("expires_on", None, b"2030-03-31"),
+                ("evidence", Some("image/png"), b"\x89PNG"),
+                ("comment", None, b"ignored"),
+            ])
+            .await,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(2, declaration.level);
+        assert_eq!(NaiveDate::from_ymd_opt(2030, 3, 31), declaration.expires_on);
+        let evidence = declaration.evidence.unwrap();
+        assert_eq!(
+            ("image/png", "png"),
+            (evidence.content_type, evidence.extension)
+        );
+        assert_eq!(&b"\x89PNG"[..], &evidence.bytes[..]);
+    }
+
+    #[tokio::test]
+    async fn should_take_empty_expiry_as_none() {
+        let declaration =
+            read_declaration(multipart(&[("level", None, b"1"), ("expires_on", None, b"")]).await)
+                .await
+                .unwrap();
+
+        assert_eq!(None, declaration.expires_on);
+        assert!(declaration.evidence.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_declaration() {
+        let status = |fields| async move {
+            status_of(
+                read_declaration(multipart(fields).await)
+                    .await
+                    .map(|_| StatusCode::OK),
+            )
+        };
+
+        assert_eq!(StatusCode::BAD_REQUEST, status(&[]).await);
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            status(&[("level", None, b"gold")]).await
+        );
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            status(&[("level", None, b"1"), ("expires_on", None, b"31/03/2030")]).await
+        );
+        assert_eq!(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            status(&[
+                ("level", None, b"1"),
+                ("evidence", Some("text/html"), b"<script>")
+            ])
+            .await
+        );
+        let large = vec![0; MAX_EVIDENCE_BYTES + 1];
+        assert_eq!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            status(&[
+                ("level", None, b"1"),
+                ("evidence", Some("image/png"), &large)
+            ])
+            .await
+        );
+    }
+
+    #[sqlx::test]
+    async fn should_replace_declaration_and_its_evidence(pool: PgPool) {
+        let program = add_program(&pool, "Dormy Inn", &["Member", "Gold"]).await;
+        let user_id = add_user(&pool, Role::Member).await;
+        let evidence_dir = evidence_dir();
+
+        for level in [b"1", b"2"] {
+            let status = status_of(
+                declare_status(
+                    claims(user_id, Role::Member),
+                    State(pool.clone()),
+                    State(evidence_dir.clone()),
+                    Path(program),
+                    multipart(&[
+                        ("level", None, level),
+                        ("evidence", Some("application/pdf"), b"%PDF"),
+                    ])
+                    .await,
+                )
+                .await,
+            );
+            assert_eq!(StatusCode::NO_CONTENT, status);
+        }
+
+        let level: i32 = sqlx::query_scalar("SELECT level FROM user_statuses WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(2, level);
+        assert_eq!(1, std::fs::read_dir(&evidence_dir).unwrap().count());
+        std::fs::remove_dir_all(evidence_dir).unwrap();
+    }
+
+    #[sqlx::test]
+    async fn should_not_override_verified_status(pool: PgPool) {
+        let program = add_program(&pool, "Dormy Inn", &["Member", "Gold"]).await;
+        let user_id = add_user(&pool, Role::Member).await;
+        sqlx::query(
+            r#"
+            INSERT INTO user_credentials (user_id, program_id, username) VALUES ($1, $2, 'member');
+            "#,
+        )
+        .bind(user_id)
+        .bind(program)
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO user_statuses (user_id, program_id, level, source) VALUES ($1, $2, 1, 'verified')",
+        )
+        .bind(user_id)
+        .bind(program)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let status = status_of(
+            declare_status(
+                claims(user_id, Role::Member),
+                State(pool.clone()),
+                State(evidence_dir()),
+                Path(program),
+                multipart(&[("level", None, b"2")]).await,
+            )
+            .await,
+        );
+        assert_eq!(StatusCode::CONFLICT, status);
+    }
+
+    #[sqlx::test]
+    async fn should_leave_out_expired_declarations(pool: PgPool) {
+        let program = add_program(&pool, "Dormy Inn", &["Member", "Gold"]).await;
+        for expires_on in ["2000-01-01", "2999-01-01"] {
+            let user_id = add_user(&pool, Role::Member).await;
+            sqlx::query(
+                r#"
+                INSERT INTO user_statuses (user_id, program_id, level, source, expires_on)
+                VALUES ($1, $2, 2, 'manual', $3::DATE)
+                "#,
+            )
+            .bind(user_id)
+            .bind(program)
+            .bind(expires_on)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            let statuses =
+                crate::get_user_statuses(claims(user_id, Role::Member), State(pool.clone()))
+                    .await
+                    .unwrap()
+                    .into_response();
+            let expired = expires_on == "2000-01-01";
+            assert_eq!(json!(expired), json_of(statuses).await[0]["expired"]);
+        }
+
+        let program = crate::get_program(State(pool.clone()), Path(program))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(
+            json!(1),
+            json_of(program).await["statuses"][1]["tracked_users"]
+        );
+    }
+}
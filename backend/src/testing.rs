@@ -0,0 +1,93 @@
+//! Fixtures of the tests that run handlers against a database of their own.
+
+use axum::{
+    body::HttpBody,
+    extract::FromRef,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::{
+    auth::{self, Claims, Role},
+    error::ApiError,
+    keys::Keys,
+};
+
+pub async fn add_user(pool: &PgPool, role: Role) -> i32 {
+    sqlx::query_scalar("INSERT INTO users (role) VALUES ($1) RETURNING id")
+        .bind(role)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+/// Adds a program whose statuses are at levels 1, 2 and so on.
+pub async fn add_program(pool: &PgPool, name: &str, statuses: &[&str]) -> i32 {
+    let id = sqlx::query_scalar("INSERT INTO programs (name) VALUES ($1) RETURNING id")
+        .bind(name)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    for (level, status) in (1..).zip(statuses) {
+        sqlx::query("INSERT INTO program_statuses (program_id, level, name) VALUES ($1, $2, $3)")
+            .bind(id)
+            .bind(level)
+            .bind(status)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    id
+}
+
+/// The state the extractors of claims need.
+#[derive(Clone, FromRef)]
+pub struct AuthState {
+    pub pool: PgPool,
+    pub keys: Arc<Keys>,
+}
+
+pub fn keys() -> Arc<Keys> {
+    dotenv::dotenv().ok();
+    Arc::new(Keys::from_env().unwrap())
+}
+
+/// Starts a session and returns its access token and refresh token.
+pub async fn login(pool: &PgPool, user_id: i32) -> (String, String) {
+    let mut conn = pool.acquire().await.unwrap();
+    let auth = auth::authorize(&mut conn, &keys(), user_id, None)
+        .await
+        .unwrap();
+    let auth = serde_json::to_value(auth).unwrap();
+    let token = |name: &str| auth[name].as_str().unwrap().to_string();
+    (token("access_token"), token("refresh_token"))
+}
+
+/// The claims of a token the extractor has accepted.
+pub fn claims(user_id: i32, role: Role) -> Claims {
+    Claims {
+        sub: user_id.to_string(),
+        exp: i64::MAX,
+        jti: String::new(),
+        role,
+        user_id,
+    }
+}
+
+pub fn status_of<T: IntoResponse>(result: Result<T, ApiError>) -> StatusCode {
+    match result {
+        Ok(response) => response.into_response().status(),
+        Err(err) => err.into_response().status(),
+    }
+}
+
+pub async fn json_of(response: Response) -> serde_json::Value {
+    let mut body = response.into_body();
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk.unwrap());
+    }
+    serde_json::from_slice(&bytes).unwrap()
+}
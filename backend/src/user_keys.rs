@@ -0,0 +1,131 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{auth::Claims, error::ApiError, lnurl, ServiceUrl};
+
+#[derive(Debug, Clone, Copy, Serialize, sqlx::Type)]
+#[sqlx(type_name = "key_type", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    Lnurl,
+    Nostr,
+}
+
+#[derive(Debug)]
+pub enum KeyError {
+    NotFound,
+    LastKey,
+}
+
+impl IntoResponse for KeyError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            KeyError::NotFound => (StatusCode::NOT_FOUND, "Key is not found"),
+            KeyError::LastKey => (
+                StatusCode::CONFLICT,
+                "The last key of an account cannot be removed",
+            ),
+        };
+
+        let body = Json(json!({
+            "error": error_message,
+        }));
+
+        (status, body).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct UserKey {
+    /// Hex encoded.
+    pubkey: String,
+    key_type: KeyType,
+    created_at: DateTime<Utc>,
+}
+
+pub async fn list_keys(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let keys = sqlx::query_as!(
+        UserKey,
+        r#"
+        SELECT
+            encode(pubkey, 'hex') AS "pubkey!",
+            key_type AS "key_type: KeyType",
+            created_at
+        FROM user_keys
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id,
+    )
+    .fetch_all(&mut conn)
+    .await?;
+
+    Ok((StatusCode::OK, Json(keys)))
+}
+
+/// Issues a challenge that adds whichever key signs it to the account, either
+/// through `/api/auth` from a wallet or `/api/auth/nostr`.
+///
+/// Listen on `/api/login/:k1/events` to learn when it has been signed.
+pub async fn link_key(
+    Claims { user_id, .. }: Claims,
+    State(service_url): State<ServiceUrl>,
+    State(pool): State<PgPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut conn = pool.acquire().await?;
+
+    let k1 = hex::encode(lnurl::create_challenge(&mut conn, Some(user_id)).await?);
+
+    let resp = json!({
+        "lnurl": lnurl::encode_lnurl(&service_url, &k1),
+        "k1": k1,
+    });
+
+    Ok((StatusCode::CREATED, Json(resp)))
+}
+
+pub async fn unlink_key(
+    Claims { user_id, .. }: Claims,
+    State(pool): State<PgPool>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut trans = pool.begin().await?;
+
+    let pubkey = hex::decode(&key).map_err(|_| KeyError::NotFound)?;
+
+    // Locks the keys of the account so that two requests cannot remove the last two.
+    let keys = sqlx::query_scalar!(
+        "SELECT pubkey FROM user_keys WHERE user_id = $1 FOR UPDATE",
+        user_id,
+    )
+    .fetch_all(&mut trans)
+    .await?;
+
+    if !keys.contains(&pubkey) {
+        return Err(KeyError::NotFound.into());
+    }
+    if keys.len() == 1 {
+        return Err(KeyError::LastKey.into());
+    }
+
+    sqlx::query!("DELETE FROM user_keys WHERE pubkey = $1", &pubkey)
+        .execute(&mut trans)
+        .await?;
+
+    trans.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
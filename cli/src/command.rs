@@ -0,0 +1,100 @@
+use anyhow::bail;
+
+const USAGE: &str = "[example] cli 'IHG One Rewards' 'Platinum Elite' | cli 'IHG One Rewards' 'Platinum Elite' 'Hilton Honors' 2 | cli --months=24 'IHG One Rewards' 'Platinum Elite' | cli sync postgres://localhost/statusmatch_poc [--full]";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Fetches from statusmatcher.com by itself, regardless of the local cache.
+    Sync {
+        db_url: String,
+        full: bool,
+    },
+    Suggest {
+        program: String,
+        status: String,
+    },
+    Plan {
+        program: String,
+        status: String,
+        to_program: String,
+        min_level: usize,
+    },
+}
+
+impl Command {
+    /// Parses the arguments after the binary name, with `--months=` already taken out.
+    pub fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let command = match args {
+            [sync, db_url] if sync == "sync" => Self::Sync {
+                db_url: db_url.clone(),
+                full: false,
+            },
+            [sync, db_url, full] if sync == "sync" && full == "--full" => Self::Sync {
+                db_url: db_url.clone(),
+                full: true,
+            },
+            [program, status] => Self::Suggest {
+                program: program.clone(),
+                status: status.clone(),
+            },
+            [program, status, to_program, min_level] => Self::Plan {
+                program: program.clone(),
+                status: status.clone(),
+                to_program: to_program.clone(),
+                min_level: min_level.parse()?,
+            },
+            _ => bail!(USAGE),
+        };
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Command> {
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+        Command::parse(&args)
+    }
+
+    #[test_case(&["IHG One Rewards", "Platinum Elite"]; "Program and status.")]
+    #[test_case(&["postgres://localhost/statusmatch_poc", "--full"]; "Looks like a sync without the subcommand.")]
+    fn should_suggest(args: &[&str]) {
+        let command = parse(args).unwrap();
+        assert_eq!(
+            Command::Suggest {
+                program: args[0].to_string(),
+                status: args[1].to_string(),
+            },
+            command
+        );
+    }
+
+    #[test_case(&["sync", "postgres://localhost/statusmatch_poc"], false; "Incremental.")]
+    #[test_case(&["sync", "postgres://localhost/statusmatch_poc", "--full"], true; "Full.")]
+    fn should_sync(args: &[&str], full: bool) {
+        let command = parse(args).unwrap();
+        assert_eq!(
+            Command::Sync {
+                db_url: args[1].to_string(),
+                full,
+            },
+            command
+        );
+    }
+
+    #[test]
+    fn should_plan() {
+        let command = parse(&["IHG One Rewards", "Platinum Elite", "Hilton Honors", "2"]).unwrap();
+        assert!(matches!(command, Command::Plan { min_level: 2, .. }));
+    }
+
+    #[test_case(&[]; "Nothing.")]
+    #[test_case(&["IHG One Rewards"]; "Program only.")]
+    #[test_case(&["IHG One Rewards", "Platinum Elite", "Hilton Honors", "two"]; "Level is not a number.")]
+    fn should_reject(args: &[&str]) {
+        assert!(parse(args).is_err());
+    }
+}
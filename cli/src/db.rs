@@ -1,70 +1,502 @@
-use sqlx::PgPool;
+use std::fmt;
 
-use crate::usecase::UsecaseForMemory;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
 
-pub async fn store(db_url: &str, usecase: &UsecaseForMemory) -> anyhow::Result<()> {
+use crate::{entities::NormalizedReportResult, scrape, usecase::UsecaseForMemory};
+
+const SOURCE: &str = "statusmatcher";
+
+#[derive(Debug, Default)]
+pub struct Tally {
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+impl Tally {
+    /// Counts the outcome of an upsert that returns `xmax = 0`, which is true
+    /// for an inserted row and false for an updated one. An upsert that
+    /// returns no row has left an identical row as is.
+    fn count(&mut self, inserted: Option<bool>) {
+        match inserted {
+            Some(true) => self.inserted += 1,
+            Some(false) => self.updated += 1,
+            None => self.skipped += 1,
+        }
+    }
+}
+
+impl fmt::Display for Tally {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} inserted, {} updated, {} skipped",
+            self.inserted, self.updated, self.skipped
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub programs: Tally,
+    pub aliases: Tally,
+    pub statuses: Tally,
+    pub reports: Tally,
+    pub watermark: Option<usize>,
+    /// Programs whose names are taken by programs from another source.
+    pub conflicting_programs: Vec<String>,
+    /// Reports between statuses that are not stored, retried on the next sync.
+    pub unstored_reports: Vec<usize>,
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Programs: {}", self.programs)?;
+        writeln!(f, "Aliases: {}", self.aliases)?;
+        writeln!(f, "Statuses: {}", self.statuses)?;
+        writeln!(f, "Reports: {}", self.reports)?;
+        match self.watermark {
+            Some(watermark) => write!(f, "Watermark: {}", watermark)?,
+            None => write!(f, "Watermark: none")?,
+        }
+        if !self.conflicting_programs.is_empty() {
+            write!(
+                f,
+                "\nWarning: skipped programs whose names are taken: {}",
+                self.conflicting_programs.join(", ")
+            )?;
+        }
+        if !self.unstored_reports.is_empty() {
+            let ids: Vec<_> = self.unstored_reports.iter().map(usize::to_string).collect();
+            write!(
+                f,
+                "\nWarning: skipped reports between unknown statuses: {}",
+                ids.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+async fn load_watermark(tx: &mut Transaction<'_, Postgres>) -> anyhow::Result<Option<usize>> {
+    let watermark = sqlx::query_scalar!(
+        "SELECT last_source_id FROM sync_watermarks WHERE source = $1",
+        SOURCE
+    )
+    .fetch_optional(tx)
+    .await?;
+    Ok(watermark.map(|id| id as usize))
+}
+
+async fn save_watermark(
+    tx: &mut Transaction<'_, Postgres>,
+    watermark: usize,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO sync_watermarks (source, last_source_id)
+        VALUES ($1, $2)
+        ON CONFLICT (source) DO UPDATE
+        SET
+            last_source_id = EXCLUDED.last_source_id,
+            synced_at = NOW()
+        "#,
+        SOURCE,
+        watermark as i32,
+    )
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+/// Fetches reports newer than the last sync and upserts them with their programs and statuses.
+///
+/// With `full`, every report is fetched again so that edited reports are updated.
+pub async fn sync(db_url: &str, full: bool) -> anyhow::Result<SyncSummary> {
     let pool = PgPool::connect(db_url).await?;
+    let mut tx = pool.begin().await?;
+
+    let watermark = load_watermark(&mut tx).await?;
+    let after = if full { None } else { watermark };
+    let usecase = UsecaseForMemory::load_from(scrape::fetch(after)?);
+
+    let summary = store(&mut tx, &usecase, watermark).await?;
+
+    tx.commit().await?;
+    Ok(summary)
+}
+
+/// Programs and statuses are found by their source ids and left as they are,
+/// so that renames and merges by admins survive. Those stored before source
+/// ids existed are matched by name and level once.
+async fn store(
+    tx: &mut Transaction<'_, Postgres>,
+    usecase: &UsecaseForMemory,
+    watermark: Option<usize>,
+) -> anyhow::Result<SyncSummary> {
+    let mut summary = SyncSummary {
+        watermark,
+        ..Default::default()
+    };
 
     for program in &usecase.programs {
-        sqlx::query!(
-            "INSERT INTO programs(name) VALUES ($1) ON CONFLICT DO NOTHING",
-            program.name
+        let inserted = sqlx::query_scalar!(
+            r#"
+            WITH attached AS (
+                UPDATE programs
+                SET source_id = $1
+                WHERE
+                    name = $2
+                    AND source_id IS NULL
+                    AND NOT EXISTS (SELECT 1 FROM programs WHERE source_id = $1)
+                RETURNING FALSE AS inserted
+            ),
+            inserted AS (
+                INSERT INTO programs (name, source_id)
+                SELECT $2, $1
+                WHERE NOT EXISTS (SELECT 1 FROM programs WHERE source_id = $1 OR name = $2)
+                ON CONFLICT DO NOTHING
+                RETURNING TRUE AS inserted
+            )
+            SELECT inserted AS "inserted!" FROM attached
+            UNION ALL
+            SELECT inserted FROM inserted
+            "#,
+            program.id as i32,
+            program.name,
         )
-        .execute(&pool)
+        .fetch_optional(&mut *tx)
         .await
-        .expect(&format!("{:?}", program));
+        .with_context(|| format!("{:?}", program))?;
+        summary.programs.count(inserted);
+
+        if inserted.is_none() {
+            let known = sqlx::query_scalar!(
+                r#"SELECT EXISTS (SELECT 1 FROM programs WHERE source_id = $1) AS "known!""#,
+                program.id as i32,
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if !known {
+                summary.conflicting_programs.push(program.name.clone());
+            }
+        }
     }
 
-    for status in &usecase.statuses {
-        let program = usecase.find_program_by_id(status.program_id)?;
+    // Programs that are not listed yet get their aliases on a later sync.
+    for (name, aliases) in search::ALIASES {
+        for alias in *aliases {
+            let inserted = sqlx::query_scalar!(
+                r#"
+                INSERT INTO program_aliases (program_id, alias)
+                SELECT id, $2 FROM programs WHERE name = $1
+                ON CONFLICT DO NOTHING
+                RETURNING (xmax = 0) AS "inserted!"
+                "#,
+                name,
+                alias
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .with_context(|| format!("{} ({})", name, alias))?;
+            summary.aliases.count(inserted);
+        }
+    }
 
-        sqlx::query!(
+    // A level that already exists gets the source id without being renamed.
+    for status in &usecase.statuses {
+        let inserted = sqlx::query_scalar!(
             r#"
-            INSERT INTO program_statuses(program_id, level, name) 
-            VALUES ((SELECT id FROM programs WHERE name = $1), $2, $3)
+            WITH program AS (
+                SELECT id FROM programs WHERE source_id = $1
+            ),
+            inserted AS (
+                INSERT INTO program_statuses (program_id, level, name)
+                SELECT id, $3, $4 FROM program
+                WHERE NOT EXISTS (SELECT 1 FROM program_status_sources WHERE source_id = $2)
+                ON CONFLICT DO NOTHING
+                RETURNING level
+            )
+            INSERT INTO program_status_sources (source_id, program_id, level)
+            SELECT $2, id, $3 FROM program
             ON CONFLICT DO NOTHING
+            RETURNING EXISTS (SELECT 1 FROM inserted) AS "inserted!"
             "#,
-            program.name,
+            status.program_id as i32,
+            status.id as i32,
             status.level as i32,
             status.name,
         )
-        .execute(&pool)
+        .fetch_optional(&mut *tx)
         .await
-        .expect(&format!("{:?}", status));
+        .with_context(|| format!("{:?}", status))?;
+        summary.statuses.count(inserted);
     }
 
+    // Reports between statuses that could not be stored are kept out of the
+    // watermark, so that they are stored once their statuses are.
     for report in &usecase.reports {
-        let from_status = usecase.find_status_by_id(report.from_status_id)?;
-        let from_program = usecase.find_program_by_id(from_status.program_id)?;
-        let to_status = usecase.find_status_by_id(report.to_status_id)?;
-        let to_program = usecase.find_program_by_id(to_status.program_id)?;
-        let result = serde_json::to_string(&report.result)?.replace("\"", "");
-
-        sqlx::query(
+        let upsert = sqlx::query!(
             r#"
-            INSERT INTO reports (
-                from_program_id,
-                from_status_level,
-                to_program_id,
-                to_status_level,
-                result
-            ) VALUES (
-                (SELECT id FROM programs WHERE name = $1),
-                $2,
-                (SELECT id FROM programs WHERE name = $3),
-                $4,
-                ($5::report_result)
+            WITH sources AS (
+                SELECT
+                    from_sources.program_id AS from_program_id,
+                    from_sources.level AS from_status_level,
+                    to_sources.program_id AS to_program_id,
+                    to_sources.level AS to_status_level
+                FROM program_status_sources AS from_sources, program_status_sources AS to_sources
+                WHERE from_sources.source_id = $2 AND to_sources.source_id = $3
+            ),
+            upserted AS (
+                INSERT INTO reports (
+                    source_id,
+                    from_program_id,
+                    from_status_level,
+                    to_program_id,
+                    to_status_level,
+                    result,
+                    created_at,
+                    notes
+                )
+                SELECT
+                    $1,
+                    from_program_id,
+                    from_status_level,
+                    to_program_id,
+                    to_status_level,
+                    $4,
+                    $5,
+                    $6
+                FROM sources
+                ON CONFLICT (source_id) DO UPDATE
+                SET
+                    from_program_id = EXCLUDED.from_program_id,
+                    from_status_level = EXCLUDED.from_status_level,
+                    to_program_id = EXCLUDED.to_program_id,
+                    to_status_level = EXCLUDED.to_status_level,
+                    result = EXCLUDED.result,
+                    created_at = EXCLUDED.created_at,
+                    notes = EXCLUDED.notes
+                WHERE
+                    (
+                        reports.from_program_id,
+                        reports.from_status_level,
+                        reports.to_program_id,
+                        reports.to_status_level,
+                        reports.result,
+                        reports.created_at,
+                        reports.notes
+                    ) IS DISTINCT FROM (
+                        EXCLUDED.from_program_id,
+                        EXCLUDED.from_status_level,
+                        EXCLUDED.to_program_id,
+                        EXCLUDED.to_status_level,
+                        EXCLUDED.result,
+                        EXCLUDED.created_at,
+                        EXCLUDED.notes
+                    )
+                RETURNING (xmax = 0) AS inserted
             )
+            SELECT
+                EXISTS (SELECT 1 FROM sources) AS "stored!",
+                (SELECT inserted FROM upserted) AS inserted
             "#,
+            report.id as i32,
+            report.from_status_id as i32,
+            report.to_status_id as i32,
+            report.result as NormalizedReportResult,
+            report.created_at,
+            report.notes,
         )
-        .bind(&from_program.name)
-        .bind(from_status.level as i32)
-        .bind(&to_program.name)
-        .bind(to_status.level as i32)
-        .bind(result)
-        .execute(&pool)
+        .fetch_one(&mut *tx)
         .await
-        .expect(&format!("{:?}", report));
+        .with_context(|| format!("{:?}", report))?;
+
+        if upsert.stored {
+            summary.reports.count(upsert.inserted);
+        } else {
+            summary.unstored_reports.push(report.id);
+        }
+    }
+
+    let first_unstored = summary.unstored_reports.iter().min().copied();
+    let latest = usecase
+        .reports
+        .iter()
+        .map(|report| report.id)
+        .filter(|id| !matches!(first_unstored, Some(first) if *id >= first))
+        .max();
+    if let Some(latest) = latest.filter(|latest| !matches!(watermark, Some(w) if w >= *latest)) {
+        save_watermark(tx, latest).await?;
+        summary.watermark = Some(latest);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::{NormalizedProgram, NormalizedReport, NormalizedStatus};
+    use sqlx::Executor;
+
+    fn usecase() -> UsecaseForMemory {
+        let program = |id, name: &str| NormalizedProgram {
+            id,
+            name: name.to_string(),
+        };
+        let status = |id, program_id, level, name: &str| NormalizedStatus {
+            id,
+            program_id,
+            level,
+            name: name.to_string(),
+        };
+        let report = |id, from_status_id, to_status_id| NormalizedReport {
+            id,
+            from_status_id,
+            to_status_id,
+            result: NormalizedReportResult::MATCH,
+            created_at: None,
+            notes: None,
+        };
+        UsecaseForMemory::load_from((
+            vec![program(1, "Hilton Honors"), program(2, "Marriott Bonvoy")],
+            vec![
+                status(10, 1, 1, "Gold"),
+                status(11, 1, 2, "Diamond"),
+                status(20, 2, 1, "Gold Elite"),
+                status(21, 2, 2, "Platinum Elite"),
+                status(22, 2, 3, "Platinum Elite Plus"),
+            ],
+            vec![report(100, 11, 21), report(101, 11, 22)],
+        ))
+    }
+
+    async fn sync_twice_around(
+        pool: &PgPool,
+        edit: &str,
+    ) -> anyhow::Result<(SyncSummary, SyncSummary)> {
+        let mut tx = pool.begin().await?;
+        let first = store(&mut tx, &usecase(), None).await?;
+        tx.execute(edit).await?;
+        let second = store(&mut tx, &usecase(), first.watermark).await?;
+        tx.commit().await?;
+        Ok((first, second))
+    }
+
+    #[sqlx::test(migrations = "../backend/migrations")]
+    async fn should_keep_renamed_program_and_status(pool: PgPool) -> anyhow::Result<()> {
+        let (first, second) = sync_twice_around(
+            &pool,
+            r#"
+            UPDATE programs SET name = 'Hilton' WHERE name = 'Hilton Honors';
+            UPDATE program_statuses SET name = 'Platinum' WHERE name = 'Platinum Elite';
+            "#,
+        )
+        .await?;
+        assert_eq!(2, first.programs.inserted);
+        assert_eq!(5, first.statuses.inserted);
+        assert_eq!(2, first.reports.inserted);
+        assert_eq!(2, second.programs.skipped);
+        assert_eq!(5, second.statuses.skipped);
+        assert_eq!(2, second.reports.skipped);
+
+        let programs: Vec<String> = sqlx::query_scalar("SELECT name FROM programs ORDER BY name")
+            .fetch_all(&pool)
+            .await?;
+        assert_eq!(vec!["Hilton", "Marriott Bonvoy"], programs);
+
+        let status: String = sqlx::query_scalar(
+            r#"
+            SELECT program_statuses.name
+            FROM program_statuses
+            INNER JOIN programs ON program_statuses.program_id = programs.id
+            WHERE programs.name = 'Marriott Bonvoy' AND level = 2
+            "#,
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!("Platinum", status);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "../backend/migrations")]
+    async fn should_keep_merged_status(pool: PgPool) -> anyhow::Result<()> {
+        // What merging Platinum Elite Plus into Platinum Elite does.
+        let (_, second) = sync_twice_around(
+            &pool,
+            r#"
+            UPDATE program_status_sources SET level = 2 WHERE source_id = 22;
+            UPDATE reports SET to_status_level = 2 WHERE source_id = 101;
+            DELETE FROM program_statuses
+            WHERE program_id = (SELECT id FROM programs WHERE source_id = 2) AND level = 3;
+            "#,
+        )
+        .await?;
+        assert_eq!(0, second.statuses.inserted);
+        assert_eq!(2, second.reports.skipped);
+
+        let levels: Vec<i32> = sqlx::query_scalar(
+            r#"
+            SELECT level
+            FROM program_statuses
+            WHERE program_id = (SELECT id FROM programs WHERE source_id = 2)
+            ORDER BY level
+            "#,
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(vec![1, 2], levels);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "../backend/migrations")]
+    async fn should_attach_rows_stored_before_source_ids(pool: PgPool) -> anyhow::Result<()> {
+        pool.execute(
+            r#"
+            INSERT INTO programs (name) VALUES ('Hilton Honors');
+            INSERT INTO program_statuses (program_id, level, name)
+            SELECT id, 1, 'Silver' FROM programs WHERE name = 'Hilton Honors';
+            "#,
+        )
+        .await?;
+
+        let mut tx = pool.begin().await?;
+        let summary = store(&mut tx, &usecase(), None).await?;
+        tx.commit().await?;
+        assert_eq!(
+            (1, 1),
+            (summary.programs.inserted, summary.programs.updated)
+        );
+        assert_eq!(
+            (4, 1),
+            (summary.statuses.inserted, summary.statuses.updated)
+        );
+
+        // The level is attached without being renamed.
+        let status: String = sqlx::query_scalar(
+            "SELECT name FROM program_statuses WHERE program_id = (SELECT id FROM programs WHERE source_id = 1) AND level = 1",
+        )
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!("Silver", status);
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "../backend/migrations")]
+    async fn should_not_advance_watermark_past_unstored_reports(
+        pool: PgPool,
+    ) -> anyhow::Result<()> {
+        pool.execute("INSERT INTO programs (name, source_id) VALUES ('Marriott Bonvoy', 99)")
+            .await?;
+
+        let mut tx = pool.begin().await?;
+        let summary = store(&mut tx, &usecase(), None).await?;
+        tx.commit().await?;
+        assert_eq!(vec!["Marriott Bonvoy"], summary.conflicting_programs);
+        assert_eq!(vec![100, 101], summary.unstored_reports);
+        assert_eq!(None, summary.watermark);
+        Ok(())
     }
-    Ok(())
 }
@@ -1,3 +1,5 @@
+use chrono::{DateTime, Utc};
+use confidence::Outcome;
 use serde::{Deserialize, Serialize};
 
 pub type Entities = (
@@ -32,10 +34,25 @@ pub enum NormalizedReportResult {
     CHALLENGE,
 }
 
+impl From<NormalizedReportResult> for Outcome {
+    fn from(result: NormalizedReportResult) -> Self {
+        match result {
+            NormalizedReportResult::MATCH => Outcome::Match,
+            NormalizedReportResult::DENY => Outcome::Deny,
+            NormalizedReportResult::CHALLENGE => Outcome::Challenge,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NormalizedReport {
     pub id: usize,
     pub from_status_id: usize,
     pub to_status_id: usize,
     pub result: NormalizedReportResult,
+    /// When the report was submitted. Dumps taken before dates were scraped lack it.
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub notes: Option<String>,
 }
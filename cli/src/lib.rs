@@ -1,3 +1,4 @@
+pub mod command;
 pub mod db;
 pub mod entities;
 pub mod scrape;
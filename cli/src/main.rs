@@ -1,30 +1,104 @@
 use std::env;
 
-use anyhow::bail;
+use anyhow::{bail, Context};
+use chrono::{Months, Utc};
 use cli::{
+    command::Command,
     db, scrape,
-    usecase::{Usecase, UsecaseForMemory},
+    usecase::{Hop, PathCriterion, Usecase, UsecaseForMemory},
 };
+use confidence::Confidence;
+
+fn describe(confidence: &Confidence) -> String {
+    format!(
+        "{:.0}% (match {} / deny {} / challenge {})",
+        confidence.probability * 100.0,
+        confidence.matches,
+        confidence.denies,
+        confidence.challenges
+    )
+}
+
+fn print_path(title: &str, hops: &[Hop]) {
+    println!("{}:", title);
+    for hop in hops {
+        println!(
+            "* {}({}) -> {}({}) {}",
+            hop.from_program.name,
+            hop.from_status.name,
+            hop.to_program.name,
+            hop.to_status.name,
+            describe(&hop.confidence)
+        );
+    }
+}
+
+fn path_differs(a: &[Hop], b: &[Hop]) -> bool {
+    a.len() != b.len()
+        || a.iter()
+            .zip(b)
+            .any(|(a, b)| a.to_status.id != b.to_status.id)
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let (months, args): (Vec<_>, Vec<_>) = env::args()
+        .skip(1)
+        .partition(|arg| arg.starts_with("--months="));
+
+    let (program, status, plan) = match Command::parse(&args)? {
+        Command::Sync { db_url, full } => {
+            let summary = db::sync(&db_url, full).await?;
+            println!("{}", summary);
+            return Ok(());
+        }
+        Command::Suggest { program, status } => (program, status, None),
+        Command::Plan {
+            program,
+            status,
+            to_program,
+            min_level,
+        } => (program, status, Some((to_program, min_level))),
+    };
+
     let data = scrape::run()?;
-    let usecase = UsecaseForMemory::load_from(data);
+    let mut usecase = UsecaseForMemory::load_from(data);
+
+    if let Some(months) = months.last() {
+        let months = months["--months=".len()..].parse()?;
+        let since = Utc::now()
+            .checked_sub_months(Months::new(months))
+            .context("--months is too large.")?;
+        usecase.retain_reports_since(since);
+    }
 
-    let args = &env::args().collect::<Vec<_>>()[..];
-    if let [_, cur_program, cur_status] = args {
-        let next_steps = usecase.suggest_next_step(&cur_program, &cur_status)?;
+    if let Some((to_program, min_level)) = plan {
+        let plan =
+            |criterion| usecase.plan_path(&program, &status, &to_program, min_level, criterion);
 
-        println!("Your next step:");
-        for (next_program, next_status) in next_steps {
-            println!("* {}({})", next_program.name, next_status.name);
+        let (Some(shortest), Some(most_reliable)) = (
+            plan(PathCriterion::Shortest)?,
+            plan(PathCriterion::MostReliable)?,
+        ) else {
+            bail!("No path is found.");
+        };
+
+        print_path("Shortest path", &shortest);
+        if path_differs(&shortest, &most_reliable) {
+            print_path("Most reliable path", &most_reliable);
         }
-        Ok(())
-    } else if let [_, db_url] = args {
-        std::env::set_var("DATABASE_URL", db_url);
-        db::store(db_url, &usecase).await?;
-        Ok(())
     } else {
-        bail!("[example] cli 'IHG One Rewards' 'Platinum Elite'")
+        let next_steps = usecase.suggest_next_step(&program, &status)?;
+
+        println!("Your next step:");
+        for (next_program, next_status, confidence) in next_steps {
+            println!(
+                "* {}({}) {}",
+                next_program.name,
+                next_status.name,
+                describe(&confidence)
+            );
+        }
     }
+    Ok(())
 }
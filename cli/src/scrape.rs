@@ -1,4 +1,6 @@
 use crate::entities::*;
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use itertools::Itertools;
 use reqwest::blocking as req;
 use serde::de::DeserializeOwned;
@@ -29,6 +31,7 @@ struct ReportList {
 }
 
 #[derive(Deserialize, Clone, Copy, Debug)]
+#[allow(clippy::upper_case_acronyms)]
 enum ReportResult {
     MATCH,
     DENY,
@@ -54,24 +57,72 @@ struct Report {
     from_status: Option<String>,
     to_program: Option<String>,
     to_status: Option<String>,
+    #[serde(default, alias = "created", alias = "createdAt", alias = "date")]
+    created_date: Option<serde_json::Value>,
+    #[serde(default, alias = "note", alias = "comment")]
+    notes: Option<String>,
+}
+
+/// Reads a submission date, which may be epoch milliseconds or an ISO 8601 string.
+fn parse_date(value: &serde_json::Value) -> Option<DateTime<Utc>> {
+    match value {
+        serde_json::Value::Number(millis) => Utc.timestamp_millis_opt(millis.as_i64()?).single(),
+        serde_json::Value::String(date) => DateTime::parse_from_rfc3339(date)
+            .map(|date| date.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f")
+                    .map(|date| Utc.from_utc_datetime(&date))
+            })
+            .or_else(|_| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map(|date| Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()))
+            })
+            .ok(),
+        _ => None,
+    }
 }
 
 fn retrieve_program_and_statuses() -> reqwest::Result<Vec<ProgramAndStatus>> {
     let url = "https://www.statusmatcher.com/api/program?view=programAndStatuses";
-    Ok(req::get(url)?.json()?)
+    req::get(url)?.json()
 }
 
-fn retrieve_reports(to_program: &NormalizedProgram) -> reqwest::Result<Vec<Report>> {
-    let url = format!("https://www.statusmatcher.com/api/report?page=0&size={}&view=programReportList&programId={}&to=true", u16::MAX, to_program.id);
-    Ok(req::get(url)?.json::<ReportList>()?.collection)
+const PAGE_SIZE: usize = 1000;
+
+/// Retrieves reports to the program whose id is greater than `after`, newest first.
+fn retrieve_reports(
+    to_program: &NormalizedProgram,
+    after: Option<usize>,
+) -> reqwest::Result<Vec<Report>> {
+    let is_new = |report: &Report| !matches!(after, Some(after) if report.id <= after);
+
+    let mut reports = vec![];
+    for page in 0.. {
+        let url = format!("https://www.statusmatcher.com/api/report?page={}&size={}&sort=id,desc&view=programReportList&programId={}&to=true", page, PAGE_SIZE, to_program.id);
+        let collection = req::get(url)?.json::<ReportList>()?.collection;
+
+        let last_page = collection.len() < PAGE_SIZE || !collection.iter().all(is_new);
+        reports.extend(collection.into_iter().filter(is_new));
+        if last_page {
+            break;
+        }
+    }
+    Ok(reports)
 }
 
-fn accumulate_reports(programs: &Vec<NormalizedProgram>) -> reqwest::Result<Vec<Report>> {
-    Ok(programs
-        .iter()
-        .filter_map(|row| retrieve_reports(row).ok())
-        .flatten()
-        .collect())
+/// Fails when reports of any program cannot be retrieved, since the watermark
+/// would otherwise move past the reports of that program.
+fn accumulate_reports(
+    programs: &[NormalizedProgram],
+    after: Option<usize>,
+) -> anyhow::Result<Vec<Report>> {
+    let mut reports = vec![];
+    for program in programs {
+        let retrieved = retrieve_reports(program, after)
+            .with_context(|| format!("Failed to retrieve reports to {}", program.name))?;
+        reports.extend(retrieved);
+    }
+    Ok(reports)
 }
 
 fn dump(path: &str, data: &impl Serialize) -> anyhow::Result<()> {
@@ -82,7 +133,7 @@ fn dump(path: &str, data: &impl Serialize) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn normalize_programs(program_and_statuses: &Vec<ProgramAndStatus>) -> Vec<NormalizedProgram> {
+fn normalize_programs(program_and_statuses: &[ProgramAndStatus]) -> Vec<NormalizedProgram> {
     program_and_statuses
         .iter()
         .unique_by(|row| &row.name)
@@ -94,8 +145,8 @@ fn normalize_programs(program_and_statuses: &Vec<ProgramAndStatus>) -> Vec<Norma
 }
 
 fn normalize_statuses(
-    programs: &Vec<NormalizedProgram>,
-    program_and_statuses: &Vec<ProgramAndStatus>,
+    programs: &[NormalizedProgram],
+    program_and_statuses: &[ProgramAndStatus],
 ) -> Vec<NormalizedStatus> {
     programs
         .iter()
@@ -118,8 +169,8 @@ fn normalize_statuses(
 }
 
 fn find_status_id(
-    programs: &Vec<NormalizedProgram>,
-    statuses: &Vec<NormalizedStatus>,
+    programs: &[NormalizedProgram],
+    statuses: &[NormalizedStatus],
     program: &Option<String>,
     status: &Option<String>,
 ) -> Option<usize> {
@@ -136,9 +187,9 @@ fn find_status_id(
 }
 
 fn normalize_reports(
-    programs: &Vec<NormalizedProgram>,
-    statuses: &Vec<NormalizedStatus>,
-    reports: &Vec<Report>,
+    programs: &[NormalizedProgram],
+    statuses: &[NormalizedStatus],
+    reports: &[Report],
 ) -> Vec<NormalizedReport> {
     reports
         .iter()
@@ -154,9 +205,15 @@ fn normalize_reports(
             ) {
                 Some(NormalizedReport {
                     id: report.id,
-                    from_status_id: from_status_id,
-                    to_status_id: to_status_id,
+                    from_status_id,
+                    to_status_id,
                     result: report.result.into(),
+                    created_at: report.created_date.as_ref().and_then(parse_date),
+                    notes: report
+                        .notes
+                        .as_ref()
+                        .map(|notes| notes.trim().to_string())
+                        .filter(|notes| !notes.is_empty()),
                 })
             } else {
                 None
@@ -183,18 +240,24 @@ const PROGRAMS_PATH: &str = "data/programs.json";
 const STATUSES_PATH: &str = "data/statuses.json";
 const REPORTS_PATH: &str = "data/reports.json";
 
+/// Retrieves every program and status, and the reports whose id is greater than `after`.
+pub fn fetch(after: Option<usize>) -> anyhow::Result<Entities> {
+    let program_and_statuses = retrieve_program_and_statuses()?;
+    let programs = normalize_programs(&program_and_statuses);
+    let statuses = normalize_statuses(&programs, &program_and_statuses);
+    let reports = accumulate_reports(&programs, after)?;
+    let reports = normalize_reports(&programs, &statuses, &reports);
+
+    Ok((programs, statuses, reports))
+}
+
 pub fn run() -> anyhow::Result<Entities> {
     let normalized_programs;
     let normalized_statuses;
     let normalized_reports;
 
     if !create_dir_if_not_exists("data")? {
-        let program_and_statuses = retrieve_program_and_statuses()?;
-        normalized_programs = normalize_programs(&program_and_statuses);
-        normalized_statuses = normalize_statuses(&normalized_programs, &program_and_statuses);
-        let reports = accumulate_reports(&normalized_programs)?;
-        normalized_reports =
-            normalize_reports(&normalized_programs, &normalized_statuses, &reports);
+        (normalized_programs, normalized_statuses, normalized_reports) = fetch(None)?;
 
         dump(PROGRAMS_PATH, &normalized_programs)?;
         dump(STATUSES_PATH, &normalized_statuses)?;
@@ -207,3 +270,89 @@ pub fn run() -> anyhow::Result<Entities> {
 
     Ok((normalized_programs, normalized_statuses, normalized_reports))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use test_case::test_case;
+
+    #[test_case(json!(1426204800000_i64), Some("2015-03-13T00:00:00Z"); "Epoch milliseconds.")]
+    #[test_case(json!("2015-03-13T09:30:00+09:00"), Some("2015-03-13T00:30:00Z"); "RFC 3339.")]
+    #[test_case(json!("2015-03-13T00:30:00.000"), Some("2015-03-13T00:30:00Z"); "Without offset.")]
+    #[test_case(json!("2015-03-13"), Some("2015-03-13T00:00:00Z"); "Date only.")]
+    #[test_case(json!("yesterday"), None; "Unknown format.")]
+    #[test_case(json!(null), None; "Null.")]
+    fn should_parse_date(value: serde_json::Value, expected: Option<&str>) {
+        let expected = expected.map(|date| date.parse::<DateTime<Utc>>().unwrap());
+        assert_eq!(expected, parse_date(&value));
+    }
+
+    #[test]
+    fn should_decode_report_list() {
+        let body = json!({
+            "collection": [
+                {
+                    "id": 5012,
+                    "result": "MATCH",
+                    "fromProgram": "Hilton Honors",
+                    "fromStatus": "Diamond",
+                    "toProgram": "Marriott Bonvoy",
+                    "toStatus": "Platinum Elite",
+                    "createdDate": 1426204800000_i64,
+                    "notes": "  Matched within a day. ",
+                },
+                {
+                    "id": 5011,
+                    "result": "DENY",
+                    "fromProgram": "Hilton Honors",
+                    "fromStatus": "Gold",
+                    "toProgram": "Marriott Bonvoy",
+                    "toStatus": "Gold Elite",
+                    "createdDate": null,
+                    "notes": null,
+                },
+            ],
+        });
+        let reports = serde_json::from_value::<ReportList>(body)
+            .unwrap()
+            .collection;
+
+        let programs = vec![
+            NormalizedProgram {
+                id: 1,
+                name: "Hilton Honors".to_string(),
+            },
+            NormalizedProgram {
+                id: 2,
+                name: "Marriott Bonvoy".to_string(),
+            },
+        ];
+        let status = |id, program_id, level, name: &str| NormalizedStatus {
+            id,
+            program_id,
+            level,
+            name: name.to_string(),
+        };
+        let statuses = vec![
+            status(10, 1, 1, "Gold"),
+            status(11, 1, 2, "Diamond"),
+            status(20, 2, 1, "Gold Elite"),
+            status(21, 2, 2, "Platinum Elite"),
+        ];
+
+        let reports = normalize_reports(&programs, &statuses, &reports);
+        assert_eq!(2, reports.len());
+        assert_eq!(
+            (11, 21),
+            (reports[0].from_status_id, reports[0].to_status_id)
+        );
+        assert_eq!(
+            Some("2015-03-13T00:00:00Z".parse::<DateTime<Utc>>().unwrap()),
+            reports[0].created_at
+        );
+        assert_eq!(Some("Matched within a day."), reports[0].notes.as_deref());
+        assert_eq!(None, reports[1].created_at);
+        assert_eq!(None, reports[1].notes);
+    }
+}
@@ -1,13 +1,100 @@
 use crate::entities::*;
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use confidence::{recency_weight, Confidence, Tally, UNDATED_WEIGHT};
 use itertools::Itertools;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathCriterion {
+    /// Fewest hops, then the most reliable among them.
+    Shortest,
+    /// Highest chance that every hop succeeds, then the fewest hops.
+    MostReliable,
+}
+
+#[derive(Debug)]
+pub struct Hop<'a> {
+    pub from_program: &'a NormalizedProgram,
+    pub from_status: &'a NormalizedStatus,
+    pub to_program: &'a NormalizedProgram,
+    pub to_status: &'a NormalizedStatus,
+    /// Reports supporting this hop.
+    pub confidence: Confidence,
+}
 
 pub trait Usecase {
     fn suggest_next_step(
         &self,
         cur_program: &str,
         cur_status: &str,
-    ) -> anyhow::Result<Vec<(&NormalizedProgram, &NormalizedStatus)>>;
+    ) -> anyhow::Result<Vec<(&NormalizedProgram, &NormalizedStatus, Confidence)>>;
+
+    /// Finds a chain of status matches from the current status to a status of
+    /// `to_program` whose level is `min_level` or higher.
+    ///
+    /// Returns `None` when no chain exists.
+    fn plan_path(
+        &self,
+        from_program: &str,
+        from_status: &str,
+        to_program: &str,
+        min_level: usize,
+        criterion: PathCriterion,
+    ) -> anyhow::Result<Option<Vec<Hop<'_>>>>;
+}
+
+/// Cost of a partial path, ordered by `criterion`.
+#[derive(Debug, Clone, Copy)]
+struct PathCost {
+    hops: usize,
+    /// Sum of `-ln(p)` over hops, where `p` is the reliability of a hop.
+    unreliability: f64,
+    criterion: PathCriterion,
+}
+
+impl PathCost {
+    fn key(&self) -> (f64, f64) {
+        match self.criterion {
+            PathCriterion::Shortest => (self.hops as f64, self.unreliability),
+            PathCriterion::MostReliable => (self.unreliability, self.hops as f64),
+        }
+    }
+
+    fn then(&self, confidence: &Confidence) -> Self {
+        Self {
+            hops: self.hops + 1,
+            unreliability: self.unreliability - confidence.probability.ln(),
+            criterion: self.criterion,
+        }
+    }
+}
+
+impl PartialEq for PathCost {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PathCost {}
+
+impl PartialOrd for PathCost {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PathCost {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().partial_cmp(&other.key()).unwrap()
+    }
+}
+
+fn report_weight(report: &NormalizedReport, now: DateTime<Utc>) -> f64 {
+    report.created_at.map_or(UNDATED_WEIGHT, |created_at| {
+        recency_weight((now - created_at).num_seconds() as f64 / 86400.0)
+    })
 }
 
 pub struct UsecaseForMemory {
@@ -19,16 +106,29 @@ pub struct UsecaseForMemory {
 impl UsecaseForMemory {
     pub fn load_from((programs, statuses, reports): Entities) -> Self {
         Self {
-            programs: programs,
-            statuses: statuses,
-            reports: reports,
+            programs,
+            statuses,
+            reports,
         }
     }
+
+    /// Drops reports submitted before `since`, including those without a date.
+    pub fn retain_reports_since(&mut self, since: DateTime<Utc>) {
+        self.reports
+            .retain(|r| matches!(r.created_at, Some(created_at) if created_at >= since));
+    }
+
+    /// The most relevant program, ranked as `/api/programs/search` does.
     fn find_program_by_name(&self, program: &str) -> anyhow::Result<&NormalizedProgram> {
-        self.programs
-            .iter()
-            .find(|p| p.name.to_lowercase().contains(&program.to_lowercase()))
-            .ok_or(anyhow!("the program is not found."))
+        search::rank(
+            program,
+            &self.programs,
+            |p| &p.name,
+            |p| search::aliases_of(&p.name).to_vec(),
+        )
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("the program is not found."))
     }
 
     fn find_status_by_name(
@@ -55,6 +155,65 @@ impl UsecaseForMemory {
             .find(|p| p.id == program_id)
             .ok_or(anyhow!("the program is not found."))
     }
+
+    /// Confidence of every (held status, reachable status) pair with at
+    /// least one MATCH report.
+    ///
+    /// A report from a lower level of the same program also applies to the
+    /// held status.
+    fn confidence_graph(&self) -> HashMap<usize, Vec<(usize, Confidence)>> {
+        let statuses: HashMap<_, _> = self.statuses.iter().map(|s| (s.id, s)).collect();
+        let statuses_by_program = self.statuses.iter().into_group_map_by(|s| s.program_id);
+
+        let now = Utc::now();
+        let mut graph: HashMap<usize, HashMap<usize, Tally>> = HashMap::new();
+        for report in &self.reports {
+            let Some(from_status) = statuses.get(&report.from_status_id) else {
+                continue;
+            };
+            for held in &statuses_by_program[&from_status.program_id] {
+                if held.level >= from_status.level {
+                    graph
+                        .entry(held.id)
+                        .or_default()
+                        .entry(report.to_status_id)
+                        .or_default()
+                        .add(report.result.into(), report_weight(report, now));
+                }
+            }
+        }
+
+        graph
+            .into_iter()
+            .map(|(from, edges)| {
+                let edges = edges
+                    .into_iter()
+                    .filter(|(_, tally)| tally.matches > 0)
+                    .map(|(to, tally)| (to, tally.into()))
+                    .collect();
+                (from, edges)
+            })
+            .collect()
+    }
+
+    /// Confidence of matching `to_status` while holding `held`.
+    pub fn confidence(&self, held: &NormalizedStatus, to_status_id: usize) -> Confidence {
+        let now = Utc::now();
+        let mut tally = Tally::default();
+        for report in self
+            .reports
+            .iter()
+            .filter(|r| r.to_status_id == to_status_id)
+        {
+            let Ok(from_status) = self.find_status_by_id(report.from_status_id) else {
+                continue;
+            };
+            if from_status.program_id == held.program_id && from_status.level <= held.level {
+                tally.add(report.result.into(), report_weight(report, now));
+            }
+        }
+        tally.into()
+    }
 }
 
 impl Usecase for UsecaseForMemory {
@@ -62,7 +221,7 @@ impl Usecase for UsecaseForMemory {
         &self,
         cur_program: &str,
         cur_status: &str,
-    ) -> anyhow::Result<Vec<(&NormalizedProgram, &NormalizedStatus)>> {
+    ) -> anyhow::Result<Vec<(&NormalizedProgram, &NormalizedStatus, Confidence)>> {
         let program = self.find_program_by_name(cur_program)?;
         let status = self.find_status_by_name(program, cur_status)?;
 
@@ -83,12 +242,82 @@ impl Usecase for UsecaseForMemory {
             .map(|r| {
                 let to_status = self.find_status_by_id(r.to_status_id).unwrap();
                 let to_program = self.find_program_by_id(to_status.program_id).unwrap();
-                (to_program, to_status)
+                (to_program, to_status, self.confidence(status, to_status.id))
             })
             .collect();
 
         Ok(result)
     }
+
+    fn plan_path(
+        &self,
+        from_program: &str,
+        from_status: &str,
+        to_program: &str,
+        min_level: usize,
+        criterion: PathCriterion,
+    ) -> anyhow::Result<Option<Vec<Hop<'_>>>> {
+        let program = self.find_program_by_name(from_program)?;
+        let start = self.find_status_by_name(program, from_status)?;
+        let goal_program = self.find_program_by_name(to_program)?;
+        let is_goal =
+            |s: &NormalizedStatus| s.program_id == goal_program.id && s.level >= min_level;
+
+        let graph = self.confidence_graph();
+        let mut best: HashMap<usize, PathCost> = HashMap::new();
+        let mut previous: HashMap<usize, (usize, Confidence)> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        let origin = PathCost {
+            hops: 0,
+            unreliability: 0.0,
+            criterion,
+        };
+        best.insert(start.id, origin);
+        queue.push(Reverse((origin, start.id)));
+
+        let mut reached = None;
+        while let Some(Reverse((cost, status_id))) = queue.pop() {
+            if matches!(best.get(&status_id), Some(b) if *b < cost) {
+                continue;
+            }
+            let status = self.find_status_by_id(status_id)?;
+            if is_goal(status) {
+                reached = Some(status_id);
+                break;
+            }
+
+            for &(next_id, confidence) in graph.get(&status_id).into_iter().flatten() {
+                let next_cost = cost.then(&confidence);
+                if !matches!(best.get(&next_id), Some(b) if *b <= next_cost) {
+                    best.insert(next_id, next_cost);
+                    previous.insert(next_id, (status_id, confidence));
+                    queue.push(Reverse((next_cost, next_id)));
+                }
+            }
+        }
+
+        let Some(mut status_id) = reached else {
+            return Ok(None);
+        };
+
+        let mut hops = vec![];
+        while let Some(&(from_id, confidence)) = previous.get(&status_id) {
+            let from_status = self.find_status_by_id(from_id)?;
+            let to_status = self.find_status_by_id(status_id)?;
+            hops.push(Hop {
+                from_program: self.find_program_by_id(from_status.program_id)?,
+                from_status,
+                to_program: self.find_program_by_id(to_status.program_id)?,
+                to_status,
+                confidence,
+            });
+            status_id = from_id;
+        }
+        hops.reverse();
+
+        Ok(Some(hops))
+    }
 }
 
 #[cfg(test)]
@@ -109,7 +338,7 @@ mod tests {
             .into_iter()
             .enumerate()
             .map(|(pos, (id, name))| NormalizedStatus {
-                id: id,
+                id,
                 level: pos,
                 name: name.to_string(),
                 program_id,
@@ -123,7 +352,9 @@ mod tests {
             id,
             from_status_id,
             to_status_id,
-            result: ReportResult::MATCH,
+            result: NormalizedReportResult::MATCH,
+            created_at: None,
+            notes: None,
         }
     }
 
@@ -136,7 +367,9 @@ mod tests {
             id,
             from_status_id,
             to_status_id,
-            result: ReportResult::DENY,
+            result: NormalizedReportResult::DENY,
+            created_at: None,
+            notes: None,
         }
     }
 
@@ -195,17 +428,25 @@ mod tests {
         (to_program, to_status): (&str, &str),
     ) {
         let usecase = create_usecase();
-        if let [(NormalizedProgram { name: program, .. }, NormalizedStatus { name: status, .. }), ..] =
+        if let [(NormalizedProgram { name: program, .. }, NormalizedStatus { name: status, .. }, _), ..] =
             usecase
                 .suggest_next_step(from_program, from_status)
                 .unwrap()[..]
         {
             assert_eq!((to_program, to_status), (program.as_str(), status.as_str()));
         } else {
-            assert!(false);
+            panic!("No suggestion.");
         }
     }
 
+    #[test_case("bonvoy", "Marriott Bonvoy"; "Alias.")]
+    #[test_case("mariott", "Marriott Bonvoy"; "Typo.")]
+    #[test_case("best western", "Best Western Rewards"; "Prefix.")]
+    fn should_find_program_by_name(name: &str, expected: &str) {
+        let usecase = create_usecase();
+        assert_eq!(expected, usecase.find_program_by_name(name).unwrap().name);
+    }
+
     #[test_case("ascott", "classic")]
     fn should_not_be_able_to_suggest(from_program: &str, from_status: &str) {
         let usecase = create_usecase();
@@ -215,4 +456,168 @@ mod tests {
 
         assert_eq!(result.into_iter().len(), 0);
     }
+
+    #[test]
+    fn should_weigh_recent_reports_more() {
+        let mut usecase = create_usecase_for_path();
+        let now = Utc::now();
+        for report in &mut usecase.reports {
+            // The direct IHG -> Hilton match is recent, while the deny is old.
+            report.created_at = Some(match report.result {
+                NormalizedReportResult::DENY => now - chrono::Duration::days(3650),
+                _ => now,
+            });
+        }
+
+        let hops = usecase
+            .plan_path(
+                "ihg",
+                "platinum elite",
+                "hilton",
+                1,
+                PathCriterion::MostReliable,
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            vec![("Platinum Elite".to_string(), "Gold".to_string(), 1)],
+            path_names(&hops)
+        );
+    }
+
+    #[test]
+    fn should_only_keep_reports_since() {
+        let mut usecase = create_usecase();
+        let now = Utc::now();
+        usecase.reports[0].created_at = Some(now - chrono::Duration::days(400));
+        usecase.reports[1].created_at = Some(now - chrono::Duration::days(10));
+
+        usecase.retain_reports_since(now - chrono::Duration::days(365));
+
+        assert_eq!(
+            vec![2],
+            usecase.reports.iter().map(|r| r.id).collect::<Vec<_>>()
+        );
+    }
+
+    fn create_usecase_for_path() -> UsecaseForMemory {
+        let (ihg, ihg_statuses) =
+            create_program_and_statuses(1, "IHG One Rewards", vec![(10, "Platinum Elite")]);
+        let (marriott, marriott_statuses) = create_program_and_statuses(
+            2,
+            "Marriott Bonvoy",
+            vec![(20, "Silver Elite"), (21, "Gold Elite")],
+        );
+        let (hilton, hilton_statuses) =
+            create_program_and_statuses(3, "Hilton Honors", vec![(30, "Silver"), (31, "Gold")]);
+        let (hyatt, hyatt_statuses) =
+            create_program_and_statuses(4, "World of Hyatt", vec![(40, "Explorist")]);
+
+        let reports = vec![
+            // IHG -> Marriott -> Hilton is well supported.
+            create_report(0, 10, 21),
+            create_report(1, 10, 21),
+            create_report(2, 10, 21),
+            create_report(3, 21, 31),
+            create_report(4, 21, 31),
+            create_report(5, 21, 31),
+            // IHG -> Hilton is direct but contested.
+            create_report(6, 10, 31),
+            create_deny_report(7, 10, 31),
+            // Silver reports also apply to Gold members.
+            create_report(8, 20, 40),
+            create_deny_report(9, 40, 10),
+        ];
+
+        UsecaseForMemory {
+            programs: vec![ihg, marriott, hilton, hyatt],
+            statuses: vec![
+                ihg_statuses,
+                marriott_statuses,
+                hilton_statuses,
+                hyatt_statuses,
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            reports,
+        }
+    }
+
+    fn path_names(hops: &[Hop]) -> Vec<(String, String, i64)> {
+        hops.iter()
+            .map(|hop| {
+                (
+                    hop.from_status.name.clone(),
+                    hop.to_status.name.clone(),
+                    hop.confidence.matches,
+                )
+            })
+            .collect()
+    }
+
+    #[test_case(PathCriterion::Shortest, vec![("Platinum Elite", "Gold", 1)]; "Direct match.")]
+    #[test_case(PathCriterion::MostReliable, vec![("Platinum Elite", "Gold Elite", 3), ("Gold Elite", "Gold", 3)]; "Detour with more confidence.")]
+    fn should_be_able_to_plan_path(criterion: PathCriterion, expected: Vec<(&str, &str, i64)>) {
+        let usecase = create_usecase_for_path();
+        let hops = usecase
+            .plan_path("ihg", "platinum elite", "hilton", 1, criterion)
+            .unwrap()
+            .unwrap();
+
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(from, to, reports)| (from.to_string(), to.to_string(), reports))
+            .collect();
+        assert_eq!(expected, path_names(&hops));
+    }
+
+    #[test]
+    fn should_use_reports_from_lower_levels() {
+        let usecase = create_usecase_for_path();
+        let hops = usecase
+            .plan_path(
+                "marriott",
+                "gold elite",
+                "hyatt",
+                0,
+                PathCriterion::Shortest,
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            vec![("Gold Elite".to_string(), "Explorist".to_string(), 1)],
+            path_names(&hops)
+        );
+    }
+
+    #[test]
+    fn should_return_empty_path_when_already_reached() {
+        let usecase = create_usecase_for_path();
+        let hops = usecase
+            .plan_path("ihg", "platinum elite", "ihg", 0, PathCriterion::Shortest)
+            .unwrap()
+            .unwrap();
+
+        assert!(hops.is_empty());
+    }
+
+    #[test_case("hyatt", "explorist", "ihg"; "Only a DENY report.")]
+    #[test_case("hilton", "gold", "marriott"; "No report.")]
+    fn should_not_be_able_to_plan_path(from_program: &str, from_status: &str, to_program: &str) {
+        let usecase = create_usecase_for_path();
+        let hops = usecase
+            .plan_path(
+                from_program,
+                from_status,
+                to_program,
+                0,
+                PathCriterion::Shortest,
+            )
+            .unwrap();
+
+        assert!(hops.is_none());
+    }
 }
@@ -0,0 +1,153 @@
+//! Report-confidence scoring shared by the backend and the CLI.
+//!
+//! The backend sums recency weights in SQL and the CLI in memory, both with
+//! `HALF_LIFE_DAYS`, and both turn the sums into a probability here.
+
+use serde::Serialize;
+
+/// Reports lose half of their weight every two years.
+pub const HALF_LIFE_DAYS: f64 = 730.0;
+
+/// Weight of a report whose submission date is unknown, as much as one that is
+/// a half-life old. Such reports are left out whenever reports are filtered by age.
+pub const UNDATED_WEIGHT: f64 = 0.5;
+
+/// Weight of a report submitted `age_days` ago.
+pub fn recency_weight(age_days: f64) -> f64 {
+    0.5_f64.powf(age_days.max(0.0) / HALF_LIFE_DAYS)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Match,
+    Deny,
+    Challenge,
+}
+
+/// Report counts and their sums of recency weights.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tally {
+    pub matches: i64,
+    pub denies: i64,
+    pub challenges: i64,
+    pub weighted_matches: f64,
+    pub weighted_denies: f64,
+    pub weighted_challenges: f64,
+}
+
+impl Tally {
+    pub fn add(&mut self, outcome: Outcome, weight: f64) {
+        match outcome {
+            Outcome::Match => {
+                self.matches += 1;
+                self.weighted_matches += weight;
+            }
+            Outcome::Deny => {
+                self.denies += 1;
+                self.weighted_denies += weight;
+            }
+            Outcome::Challenge => {
+                self.challenges += 1;
+                self.weighted_challenges += weight;
+            }
+        }
+    }
+
+    /// Success probability with one imaginary match and one imaginary deny as
+    /// the prior, so that a single MATCH is not taken as certainty.
+    ///
+    /// A challenge counts as half a match because it still needs stays to complete.
+    pub fn probability(&self) -> f64 {
+        let successes = self.weighted_matches + 0.5 * self.weighted_challenges;
+        let total = self.weighted_matches + self.weighted_denies + self.weighted_challenges;
+        (successes + 1.0) / (total + 2.0)
+    }
+}
+
+/// How likely a status match succeeds, judging from reports between two statuses.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Confidence {
+    pub matches: i64,
+    pub denies: i64,
+    pub challenges: i64,
+    pub probability: f64,
+}
+
+impl From<Tally> for Confidence {
+    fn from(tally: Tally) -> Self {
+        Self {
+            matches: tally.matches,
+            denies: tally.denies,
+            challenges: tally.challenges,
+            probability: tally.probability(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn tally(outcomes: &[(Outcome, f64)]) -> Tally {
+        let mut tally = Tally::default();
+        for &(outcome, weight) in outcomes {
+            tally.add(outcome, weight);
+        }
+        tally
+    }
+
+    #[test_case(&[], 0.5; "No report.")]
+    #[test_case(&[(Outcome::Match, 1.0)], 2.0 / 3.0; "One match.")]
+    #[test_case(&[(Outcome::Match, 1.0), (Outcome::Deny, 1.0)], 0.5; "Match and deny.")]
+    #[test_case(&[(Outcome::Challenge, 1.0)], 0.5; "One challenge.")]
+    fn should_score(outcomes: &[(Outcome, f64)], expected: f64) {
+        assert!((tally(outcomes).probability() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_prefer_recent_reports() {
+        let recent_match = tally(&[
+            (Outcome::Match, recency_weight(30.0)),
+            (Outcome::Deny, recency_weight(3000.0)),
+        ]);
+        let old_match = tally(&[
+            (Outcome::Match, recency_weight(3000.0)),
+            (Outcome::Deny, recency_weight(30.0)),
+        ]);
+
+        assert!(recent_match.probability() > 0.5);
+        assert!(old_match.probability() < 0.5);
+        assert!((recency_weight(HALF_LIFE_DAYS) - 0.5).abs() < 1e-9);
+        assert!((recency_weight(HALF_LIFE_DAYS) - UNDATED_WEIGHT).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_keep_counts_and_score_weights_of_tally() {
+        // As aggregated in SQL: two old denies outweighed by a recent match.
+        let confidence = Confidence::from(Tally {
+            matches: 1,
+            denies: 2,
+            challenges: 0,
+            weighted_matches: 1.0,
+            weighted_denies: 0.25,
+            weighted_challenges: 0.0,
+        });
+
+        assert_eq!(
+            (1, 2, 0),
+            (confidence.matches, confidence.denies, confidence.challenges)
+        );
+        assert!((confidence.probability - 2.0 / 3.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_score_empty_tally_as_even() {
+        let confidence = Confidence::from(Tally::default());
+        assert_eq!(
+            0,
+            confidence.matches + confidence.denies + confidence.challenges
+        );
+        assert!((confidence.probability - 0.5).abs() < 1e-9);
+    }
+}
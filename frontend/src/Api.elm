@@ -2,6 +2,7 @@ module Api exposing (..)
 
 import Http
 import Json.Decode as D
+import Json.Encode as E
 
 
 type alias Challenge =
@@ -16,9 +17,15 @@ type alias TokenType =
     String
 
 
+type alias RefreshToken =
+    String
+
+
 type alias Auth =
     { accessToken : AccessToken
     , tokenType : TokenType
+    , expiresIn : Int
+    , refreshToken : RefreshToken
     }
 
 
@@ -65,9 +72,11 @@ lnurlAuthDecoder =
 
 authDecoder : D.Decoder Auth
 authDecoder =
-    D.map2 Auth
+    D.map4 Auth
         (D.field "access_token" D.string)
         (D.field "token_type" D.string)
+        (D.field "expires_in" D.int)
+        (D.field "refresh_token" D.string)
 
 
 programDecoder : D.Decoder Program_
@@ -79,7 +88,7 @@ programDecoder =
 
 programListDecoder : D.Decoder (List Program_)
 programListDecoder =
-    D.list programDecoder
+    D.field "programs" (D.list programDecoder)
 
 
 statusDecoder : D.Decoder Status
@@ -134,6 +143,17 @@ fetchLnurlAuthState { k1 } tagger =
         }
 
 
+{-| Replaces both tokens. The old ones stop working at once.
+-}
+refreshAuth : Auth -> (Result Http.Error Auth -> msg) -> Cmd msg
+refreshAuth { refreshToken } tagger =
+    Http.post
+        { url = "api/token/refresh"
+        , body = Http.jsonBody (E.object [ ( "refresh_token", E.string refreshToken ) ])
+        , expect = Http.expectJson tagger authDecoder
+        }
+
+
 fetchPrograms : String -> (Result Http.Error (List Program_) -> msg) -> Cmd msg
 fetchPrograms text tagger =
     Http.get
@@ -51,6 +51,8 @@ type Msg
     | UpdateAuthState LnurlAuth
     | LoadLnurlAuth (Result Http.Error LnurlAuth)
     | LoadAuth (Result Http.Error Auth)
+    | RefreshAuth Auth
+    | LoadRefreshedAuth (Result Http.Error Auth)
     | LoadPrograms AccountListForm (Result Http.Error (List Program_))
     | LoadStatuses AccountListForm (Result Http.Error (List Status))
     | LoadLinks (Result Http.Error (List Link))
@@ -110,6 +112,15 @@ update msg model =
         LoadAuth (Err _) ->
             ( model, Cmd.none )
 
+        RefreshAuth auth ->
+            ( model, refreshAuth auth LoadRefreshedAuth )
+
+        LoadRefreshedAuth (Ok auth) ->
+            ( { model | authState = Authenticated auth }, Cmd.none )
+
+        LoadRefreshedAuth (Err _) ->
+            ( { model | authState = Unauthenticated }, Cmd.none )
+
         LoadPrograms form (Ok programs) ->
             let
                 newForm =
@@ -265,6 +276,10 @@ subscriptions model =
         Authenticating lnurlAuth ->
             Time.every 1000 (always <| UpdateAuthState lnurlAuth)
 
+        -- A minute before the access token expires.
+        Authenticated auth ->
+            Time.every (toFloat (max 1 (auth.expiresIn - 60)) * 1000) (always <| RefreshAuth auth)
+
         _ ->
             Sub.none
 
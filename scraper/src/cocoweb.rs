@@ -1,53 +1,104 @@
-use anyhow::bail;
-use headless_chrome::{Browser, LaunchOptionsBuilder};
-
-fn translate_status(status: &str) -> anyhow::Result<String> {
-    match status {
-        "レギュラーステージ" => Ok("Regular Stage".to_string()),
-        "ブロンドステージ" => Ok("Bronze Stage".to_string()),
-        "シルバーステージ" => Ok("Silver Stage".to_string()),
-        "ゴールドステージ" => Ok("Gold Stage".to_string()),
-        "プラチナステージ" => Ok("Platina Stage".to_string()),
-        _ => bail!("Status translation failed."),
+use headless_chrome::Tab;
+
+use crate::{
+    error::{OrFail, ScrapeError, ScrapeFailure},
+    registry::ProgramScraper,
+};
+
+pub struct Cocoweb;
+
+impl ProgramScraper for Cocoweb {
+    fn slug(&self) -> &'static str {
+        "cocoweb"
     }
-}
 
-pub fn retrieve_status(email: &str, password: &str) -> anyhow::Result<String> {
-    let launch_options = LaunchOptionsBuilder::default().sandbox(false).build()?;
-    let browser = Browser::new(launch_options)?;
+    fn login(&self, tab: &Tab, username: &str, password: &str) -> Result<(), ScrapeError> {
+        tab.navigate_to("https://coco-web.jp/users/login")
+            .or_fail(ScrapeFailure::Timeout)?;
 
-    let tab = browser.new_tab()?;
-    tab.enable_stealth_mode()?;
+        tab.wait_for_element("input[name=email]")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .click()?;
+        tab.type_str(username)?;
+        tab.wait_for_element("input[name=password]")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .click()?;
+        tab.type_str(password)?;
 
-    tab.navigate_to("https://coco-web.jp/users/login")?;
+        tab.wait_for_element("button[type=submit]")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .click()?;
 
-    tab.wait_for_element("input[name=email]")?.click()?;
-    tab.type_str(email)?;
-    tab.wait_for_element("input[name=password]")?.click()?;
-    tab.type_str(password)?;
+        // 利用規約の更新について
+        if let Ok(el) = tab.wait_for_element("button[type=submit]") {
+            el.click()?;
+        }
 
-    tab.wait_for_element("button[type=submit]")?.click()?;
+        // The stage is only shown on the page after login.
+        if let Err(err) = tab.wait_for_element(".stage_area") {
+            // ログインに失敗するとログインフォームが再表示される
+            if tab.find_element("input[name=password]").is_ok() {
+                return Err(ScrapeError::new(
+                    ScrapeFailure::BadCredentials,
+                    "The login form is shown again.",
+                ));
+            }
+            return Err(ScrapeError::new(
+                ScrapeFailure::LayoutChanged,
+                err.to_string(),
+            ));
+        }
 
-    // 利用規約の更新について
-    if let Ok(el) = tab.wait_for_element("button[type=submit]") {
-        el.click()?;
+        Ok(())
     }
 
-    let status = tab.wait_for_element(".stage_area")?.get_inner_text()?;
+    fn fetch_status(&self, tab: &Tab) -> Result<String, ScrapeError> {
+        Ok(tab
+            .wait_for_element(".stage_area")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .get_inner_text()?)
+    }
 
-    translate_status(&status)
+    fn translate_status(&self, status: &str) -> Result<String, ScrapeError> {
+        match status {
+            "レギュラーステージ" => Ok("Regular Stage".to_string()),
+            "ブロンドステージ" => Ok("Bronze Stage".to_string()),
+            "シルバーステージ" => Ok("Silver Stage".to_string()),
+            "ゴールドステージ" => Ok("Gold Stage".to_string()),
+            "プラチナステージ" => Ok("Platina Stage".to_string()),
+            _ => Err(ScrapeError::new(
+                ScrapeFailure::UnknownStatus,
+                format!("Status translation failed: {}", status),
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn should_classify_unknown_status_text() {
+        assert_eq!(
+            "Bronze Stage",
+            Cocoweb.translate_status("ブロンドステージ").unwrap()
+        );
+        assert_eq!(
+            ScrapeFailure::UnknownStatus,
+            Cocoweb
+                .translate_status("ダイヤモンドステージ")
+                .unwrap_err()
+                .failure
+        );
+    }
+
     #[test]
     #[ignore]
     fn can_get_status() {
-        let email = todo!("Input test email");
-        let password = todo!("Input test password");
-        let status = retrieve_status(email, password).unwrap();
+        let email = std::env::var("COCOWEB_EMAIL").expect("COCOWEB_EMAIL must be set.");
+        let password = std::env::var("COCOWEB_PASSWORD").expect("COCOWEB_PASSWORD must be set.");
+        let status = Cocoweb.retrieve_status(&email, &password).unwrap();
         assert_eq!("Regular Stage".to_string(), status);
     }
 }
@@ -0,0 +1,317 @@
+use std::{env, sync::Arc, time::Duration};
+
+use sqlx::PgPool;
+
+use crate::{
+    error::ScrapeFailure, record_result, registry::Registry, renew_status, vault::Keyring,
+    Credential, ScrapeResult, SCRAPE_TIMEOUT,
+};
+
+pub struct DaemonConfig {
+    /// Number of workers in this process.
+    pub workers: usize,
+    /// How often each credential is refreshed.
+    pub interval: Duration,
+    /// Random delay added to `interval` so that refreshes do not bunch up.
+    pub jitter: Duration,
+    /// First retry delay after a failure, doubled on every attempt.
+    pub retry_base: Duration,
+    /// How long an idle worker sleeps before looking for jobs again.
+    pub poll_interval: Duration,
+}
+
+fn env_secs(key: &str, default: u64) -> anyhow::Result<Duration> {
+    let secs = match env::var(key) {
+        Ok(secs) => secs.parse()?,
+        Err(_) => default,
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+impl DaemonConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let workers = match env::var("SCRAPER_WORKERS") {
+            Ok(workers) => workers.parse()?,
+            Err(_) => 1,
+        };
+
+        Ok(Self {
+            workers,
+            interval: env_secs("SCRAPE_INTERVAL_SECS", 24 * 60 * 60)?,
+            jitter: env_secs("SCRAPE_JITTER_SECS", 60 * 60)?,
+            retry_base: env_secs("SCRAPE_RETRY_BASE_SECS", 5 * 60)?,
+            poll_interval: env_secs("SCRAPE_POLL_INTERVAL_SECS", 60)?,
+        })
+    }
+
+    /// Delay before retrying a job that has failed `attempts` times in a row.
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exp = attempts.saturating_sub(1).clamp(0, 16) as u32;
+        self.retry_base
+            .saturating_mul(2u32.pow(exp))
+            .min(self.interval)
+    }
+}
+
+struct Job {
+    user_id: i32,
+    program_id: i32,
+    attempts: i32,
+}
+
+/// Adds a job for every credential that does not have one yet.
+///
+/// The first runs are spread over `jitter`, so that credentials enqueued
+/// together, e.g. when the daemon first starts, are not all scraped at once.
+async fn enqueue(pool: &PgPool, jitter: Duration) -> sqlx::Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO scrape_jobs (user_id, program_id, run_at)
+        SELECT user_id, program_id, NOW() + make_interval(secs => random() * $1)
+        FROM user_credentials
+        ON CONFLICT DO NOTHING
+        "#,
+        jitter.as_secs_f64(),
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Leases the most overdue job.
+///
+/// `SKIP LOCKED` lets concurrent workers, in this process or another, claim
+/// different jobs. The lease outlives a scrape, so a job whose worker died is
+/// picked up again once it expires.
+async fn claim(pool: &PgPool) -> sqlx::Result<Option<Job>> {
+    let lease = SCRAPE_TIMEOUT.as_secs_f64() * 2.0;
+
+    sqlx::query_as!(
+        Job,
+        r#"
+        WITH next_job AS (
+            SELECT user_id, program_id
+            FROM scrape_jobs
+            WHERE
+                run_at <= NOW()
+                AND (locked_until IS NULL OR locked_until < NOW())
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        UPDATE scrape_jobs
+        SET locked_until = NOW() + make_interval(secs => $1)
+        FROM next_job
+        WHERE
+            scrape_jobs.user_id = next_job.user_id
+            AND scrape_jobs.program_id = next_job.program_id
+        RETURNING
+            scrape_jobs.user_id,
+            scrape_jobs.program_id,
+            scrape_jobs.attempts
+        "#,
+        lease,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_credential(pool: &PgPool, job: &Job) -> sqlx::Result<Option<Credential>> {
+    sqlx::query_as!(
+        Credential,
+        r#"
+        SELECT
+            user_credentials.user_id,
+            user_credentials.program_id,
+            programs.slug,
+            user_credentials.username,
+            user_credentials.password_ciphertext,
+            user_credentials.data_key,
+            user_credentials.key_version
+        FROM user_credentials
+        INNER JOIN programs
+            ON user_credentials.program_id = programs.id
+        WHERE
+            user_credentials.user_id = $1
+            AND user_credentials.program_id = $2
+        "#,
+        job.user_id,
+        job.program_id,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn reschedule(
+    pool: &PgPool,
+    job: &Job,
+    delay: Duration,
+    jitter: Duration,
+    attempts: i32,
+    last_error: Option<&str>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE scrape_jobs
+        SET
+            run_at = NOW() + make_interval(secs => $3 + random() * $4),
+            attempts = $5,
+            locked_until = NULL,
+            last_error = $6
+        WHERE
+            user_id = $1
+            AND program_id = $2
+        "#,
+        job.user_id,
+        job.program_id,
+        delay.as_secs_f64(),
+        jitter.as_secs_f64(),
+        attempts,
+        last_error,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn process(
+    pool: &PgPool,
+    registry: &Registry,
+    keyring: &Keyring,
+    config: &DaemonConfig,
+    job: Job,
+) -> sqlx::Result<()> {
+    // The credential has been deleted since the job was claimed.
+    let Some(credential) = fetch_credential(pool, &job).await? else {
+        return Ok(());
+    };
+
+    let outcome = renew_status(pool, registry, keyring, &credential).await;
+
+    match &outcome {
+        Ok(_) => reschedule(pool, &job, config.interval, config.jitter, 0, None).await?,
+        Err(err) => {
+            tracing::warn!(
+                program_id = job.program_id,
+                attempts = job.attempts + 1,
+                failure = ?err.failure,
+                "{}",
+                err.message
+            );
+
+            // Retrying does not help until the user fixes the credential.
+            let delay = match err.failure {
+                ScrapeFailure::BadCredentials | ScrapeFailure::UnknownStatus => config.interval,
+                _ => config.backoff(job.attempts + 1),
+            };
+            reschedule(
+                pool,
+                &job,
+                delay,
+                Duration::ZERO,
+                job.attempts + 1,
+                Some(&err.message),
+            )
+            .await?
+        }
+    }
+
+    let result = ScrapeResult {
+        user_id: job.user_id,
+        program_id: job.program_id,
+        outcome,
+    };
+    record_result(pool, None, &result).await
+}
+
+async fn work(
+    pool: PgPool,
+    registry: Arc<Registry>,
+    keyring: Arc<Keyring>,
+    config: Arc<DaemonConfig>,
+) {
+    loop {
+        let claimed = claim(&pool).await;
+
+        match claimed {
+            Ok(Some(job)) => {
+                if let Err(err) = process(&pool, &registry, &keyring, &config, job).await {
+                    tracing::error!("Failed to process a job: {}", err);
+                }
+            }
+            Ok(None) => tokio::time::sleep(config.poll_interval).await,
+            Err(err) => {
+                tracing::error!("Failed to claim a job: {}", err);
+                tokio::time::sleep(config.poll_interval).await;
+            }
+        }
+    }
+}
+
+async fn schedule(pool: PgPool, config: Arc<DaemonConfig>) {
+    loop {
+        match enqueue(&pool, config.jitter).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!(count, "Jobs have been enqueued."),
+            Err(err) => tracing::error!("Failed to enqueue jobs: {}", err),
+        }
+        tokio::time::sleep(config.poll_interval).await;
+    }
+}
+
+/// Refreshes credentials on schedule until interrupted.
+///
+/// Several daemons can share the same database.
+pub async fn run(
+    db_url: &str,
+    registry: Registry,
+    keyring: Keyring,
+    config: DaemonConfig,
+) -> anyhow::Result<()> {
+    let pool = PgPool::connect(db_url).await?;
+    let registry = Arc::new(registry);
+    let keyring = Arc::new(keyring);
+    let config = Arc::new(config);
+
+    tracing::info!(workers = config.workers, "The daemon has started.");
+
+    let mut tasks = vec![tokio::spawn(schedule(pool.clone(), config.clone()))];
+    for _ in 0..config.workers {
+        tasks.push(tokio::spawn(work(
+            pool.clone(),
+            registry.clone(),
+            keyring.clone(),
+            config.clone(),
+        )));
+    }
+
+    tokio::signal::ctrl_c().await?;
+
+    tracing::info!("The daemon is shutting down.");
+    for task in tasks {
+        task.abort();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_back_off_exponentially_up_to_interval() {
+        let config = DaemonConfig {
+            workers: 1,
+            interval: Duration::from_secs(3600),
+            jitter: Duration::ZERO,
+            retry_base: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(60),
+        };
+
+        assert_eq!(Duration::from_secs(60), config.backoff(1));
+        assert_eq!(Duration::from_secs(120), config.backoff(2));
+        assert_eq!(Duration::from_secs(480), config.backoff(4));
+        assert_eq!(Duration::from_secs(3600), config.backoff(10));
+        assert_eq!(Duration::from_secs(3600), config.backoff(i32::MAX));
+    }
+}
@@ -1,45 +1,84 @@
-use anyhow::bail;
-use headless_chrome::{Browser, LaunchOptionsBuilder};
-
-fn translate_status(status: &str) -> anyhow::Result<String> {
-    match status {
-        "メンバー" => Ok("Member".to_string()),
-        "シルバー" => Ok("Silver".to_string()),
-        "ゴールド" => Ok("Gold".to_string()),
-        _ => bail!("Status translation failed."),
-    }
-}
+use headless_chrome::Tab;
+
+use crate::{
+    error::{OrFail, ScrapeError, ScrapeFailure},
+    registry::ProgramScraper,
+};
 
-pub fn retrieve_status(email: &str, password: &str) -> anyhow::Result<String> {
-    let launch_options = LaunchOptionsBuilder::default().sandbox(false).build()?;
-    let browser = Browser::new(launch_options)?;
+pub struct Dormys;
+
+impl ProgramScraper for Dormys {
+    fn slug(&self) -> &'static str {
+        "dormys"
+    }
 
-    let tab = browser.new_tab()?;
-    tab.enable_stealth_mode()?;
+    fn login(&self, tab: &Tab, username: &str, password: &str) -> Result<(), ScrapeError> {
+        tab.navigate_to("https://www.hotespa.net/dormyinn/")
+            .or_fail(ScrapeFailure::Timeout)?;
 
-    tab.navigate_to("https://www.hotespa.net/dormyinn/")?;
+        tab.wait_for_element(".logent > a")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .click()?;
 
-    tab.wait_for_element(".logent > a")?.click()?;
+        tab.wait_for_element("input[name=mailAddress]")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .click()?;
+        tab.type_str(username)?;
+        tab.wait_for_element("input[name=password]")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .click()?;
+        tab.type_str(password)?;
 
-    tab.wait_for_element("input[name=mailAddress]")?.click()?;
-    tab.type_str(email)?;
-    tab.wait_for_element("input[name=password]")?.click()?;
-    tab.type_str(password)?;
+        tab.wait_for_element(".formSubmit")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .click()?;
 
-    tab.wait_for_element(".formSubmit")?.click()?;
+        if let Ok(el) = tab.wait_for_element("#warnOkButton") {
+            el.click()?;
+        }
 
-    if let Ok(el) = tab.wait_for_element("#warnOkButton") {
-        el.click()?;
+        tab.wait_for_element("a[href*=mypage]")
+            .or_fail(ScrapeFailure::BadCredentials)?;
+        Ok(())
     }
 
-    tab.wait_for_element("a[href*=mypage]")?;
+    fn fetch_status(&self, tab: &Tab) -> Result<String, ScrapeError> {
+        tab.navigate_to("https://www.kyoritsumembers.com/secure/mypage/member")
+            .or_fail(ScrapeFailure::Timeout)?;
 
-    tab.navigate_to("https://www.kyoritsumembers.com/secure/mypage/member")?;
+        if let Ok(el) = tab.wait_for_element("#warnOkButton") {
+            el.click()?;
+        }
 
-    if let Ok(el) = tab.wait_for_element("#warnOkButton") {
-        el.click()?;
+        Ok(tab
+            .wait_for_element(".serviceType")
+            .or_fail(ScrapeFailure::LayoutChanged)?
+            .get_inner_text()?)
     }
 
-    let status = tab.wait_for_element(".serviceType")?.get_inner_text()?;
-    translate_status(&status)
+    fn translate_status(&self, status: &str) -> Result<String, ScrapeError> {
+        match status {
+            "メンバー" => Ok("Member".to_string()),
+            "シルバー" => Ok("Silver".to_string()),
+            "ゴールド" => Ok("Gold".to_string()),
+            _ => Err(ScrapeError::new(
+                ScrapeFailure::UnknownStatus,
+                format!("Status translation failed: {}", status),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_classify_unknown_status_text() {
+        assert_eq!("Silver", Dormys.translate_status("シルバー").unwrap());
+        assert_eq!(
+            ScrapeFailure::UnknownStatus,
+            Dormys.translate_status("プラチナ").unwrap_err().failure
+        );
+    }
 }
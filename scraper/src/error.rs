@@ -0,0 +1,61 @@
+use std::fmt;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "scrape_failure", rename_all = "snake_case")]
+pub enum ScrapeFailure {
+    BadCredentials,
+    LayoutChanged,
+    UnknownStatus,
+    Timeout,
+    Other,
+}
+
+#[derive(Debug)]
+pub struct ScrapeError {
+    pub failure: ScrapeFailure,
+    pub message: String,
+}
+
+impl ScrapeError {
+    pub fn new(failure: ScrapeFailure, message: impl Into<String>) -> Self {
+        Self {
+            failure,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.failure, self.message)
+    }
+}
+
+impl std::error::Error for ScrapeError {}
+
+/// Errors that are not classified by a scraper are reported as `Other`.
+impl From<anyhow::Error> for ScrapeError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::new(ScrapeFailure::Other, err.to_string())
+    }
+}
+
+impl From<sqlx::Error> for ScrapeError {
+    fn from(err: sqlx::Error) -> Self {
+        Self::new(ScrapeFailure::Other, err.to_string())
+    }
+}
+
+pub trait OrFail<T> {
+    /// Classifies the error as `failure`.
+    fn or_fail(self, failure: ScrapeFailure) -> Result<T, ScrapeError>;
+}
+
+impl<T> OrFail<T> for anyhow::Result<T> {
+    fn or_fail(self, failure: ScrapeFailure) -> Result<T, ScrapeError> {
+        self.map_err(|err| ScrapeError::new(failure, err.to_string()))
+    }
+}
@@ -1,59 +1,558 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
 use serde::Deserialize;
 use sqlx::PgPool;
 
 mod cocoweb;
+pub mod daemon;
 mod dormys;
+pub mod error;
+pub mod registry;
+pub mod vault;
+
+use error::{ScrapeError, ScrapeFailure};
+use registry::{ProgramScraper, Registry};
+use vault::{Keyring, Sealed};
+
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(180);
 
 #[derive(Deserialize)]
 struct Credential {
-    user_pubkey: Vec<u8>,
+    user_id: i32,
     program_id: i32,
+    slug: Option<String>,
+    username: String,
+    password_ciphertext: Option<Vec<u8>>,
+    data_key: Option<Vec<u8>>,
+    key_version: Option<i32>,
+}
+
+pub fn sealed_password(
+    ciphertext: &Option<Vec<u8>>,
+    data_key: &Option<Vec<u8>>,
+    key_version: Option<i32>,
+) -> Option<Sealed> {
+    match (ciphertext, data_key, key_version) {
+        (Some(ciphertext), Some(data_key), Some(key_version)) => Some(Sealed {
+            ciphertext: ciphertext.clone(),
+            data_key: data_key.clone(),
+            key_version,
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct ScrapeResult {
+    pub user_id: i32,
+    pub program_id: i32,
+    pub outcome: Result<String, ScrapeError>,
+}
+
+#[derive(Debug)]
+pub struct ScrapeReport {
+    pub run_id: i32,
+    pub results: Vec<ScrapeResult>,
+}
+
+impl ScrapeReport {
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.outcome.is_ok()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|r| r.outcome.is_err()).count()
+    }
+}
+
+async fn scrape(
+    registry: &Registry,
+    keyring: &Keyring,
+    credential: &Credential,
+) -> Result<String, ScrapeError> {
+    let scraper = credential
+        .slug
+        .as_deref()
+        .and_then(|slug| registry.get(slug))
+        .ok_or_else(|| {
+            ScrapeError::new(
+                ScrapeFailure::Other,
+                "No scraper is registered for the program.",
+            )
+        })?;
+
+    let sealed = sealed_password(
+        &credential.password_ciphertext,
+        &credential.data_key,
+        credential.key_version,
+    )
+    .ok_or_else(|| {
+        ScrapeError::new(
+            ScrapeFailure::Other,
+            "The password is not encrypted yet. Run `scraper rekey`.",
+        )
+    })?;
+
+    let password = keyring.decrypt(&sealed)?;
+    retrieve_status(scraper, credential.username.clone(), password).await
+}
+
+/// Runs `scraper` on a blocking thread, giving up after `SCRAPE_TIMEOUT`.
+///
+/// A blocking thread cannot be cancelled, so on timeout the scraper keeps
+/// running in the background. Every wait of `headless_chrome` gives up on its
+/// own, and Chrome is killed once the scraper returns and drops its `Browser`.
+pub async fn retrieve_status(
+    scraper: Arc<dyn ProgramScraper>,
     username: String,
     password: String,
+) -> Result<String, ScrapeError> {
+    retrieve_status_holding(scraper, username, password, ()).await
 }
 
-pub async fn renew_statuses(db_url: &str) {
-    let pool = PgPool::connect(&db_url).await.unwrap();
+/// As `retrieve_status`, dropping `guard` only once the scraper returns, so
+/// that a limit on running scrapers still holds after a timeout.
+pub async fn retrieve_status_holding<G: Send + 'static>(
+    scraper: Arc<dyn ProgramScraper>,
+    username: String,
+    password: String,
+    guard: G,
+) -> Result<String, ScrapeError> {
+    retrieve_status_within(scraper, username, password, guard, SCRAPE_TIMEOUT).await
+}
 
-    let credentials = sqlx::query_as!(Credential, "SELECT * FROM user_credentials",)
-        .fetch_all(&pool)
-        .await
-        .unwrap();
-
-    for credential in credentials {
-        let status = if credential.program_id == 147 {
-            dormys::retrieve_status(&credential.username, &credential.password).unwrap()
-        } else if credential.program_id == 148 {
-            cocoweb::retrieve_status(&credential.username, &credential.password).unwrap()
-        } else {
-            unimplemented!("Unimpemented Program")
+async fn retrieve_status_within<G: Send + 'static>(
+    scraper: Arc<dyn ProgramScraper>,
+    username: String,
+    password: String,
+    guard: G,
+    timeout: Duration,
+) -> Result<String, ScrapeError> {
+    let task = tokio::task::spawn_blocking(move || {
+        let status = scraper.retrieve_status(&username, &password);
+        drop(guard);
+        status
+    });
+
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(err)) => Err(ScrapeError::new(ScrapeFailure::Other, err.to_string())),
+        Err(_) => Err(ScrapeError::new(
+            ScrapeFailure::Timeout,
+            "Scraping did not finish in time.",
+        )),
+    }
+}
+
+async fn update_status(
+    pool: &PgPool,
+    credential: &Credential,
+    status: &str,
+) -> Result<(), ScrapeError> {
+    let level = sqlx::query_scalar!(
+        "SELECT level FROM program_statuses WHERE program_id = $1 AND name = $2",
+        credential.program_id,
+        status,
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        ScrapeError::new(
+            ScrapeFailure::UnknownStatus,
+            format!("{} is not found in program_statuses.", status),
+        )
+    })?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_statuses (user_id, program_id, level, source)
+        VALUES ($1, $2, $3, 'verified')
+        ON CONFLICT (user_id, program_id)
+        DO UPDATE
+            SET
+                level = $3,
+                source = 'verified',
+                -- A declared expiry no longer applies once the program vouches for the level.
+                expires_on = NULL,
+                updated_at = NOW()
+        "#,
+        credential.user_id,
+        credential.program_id,
+        level,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn renew_status(
+    pool: &PgPool,
+    registry: &Registry,
+    keyring: &Keyring,
+    credential: &Credential,
+) -> Result<String, ScrapeError> {
+    let status = scrape(registry, keyring, credential).await?;
+    update_status(pool, credential, &status).await?;
+    Ok(status)
+}
+
+/// `run_id` is `None` for results of the daemon, which has no runs.
+async fn record_result(
+    pool: &PgPool,
+    run_id: Option<i32>,
+    result: &ScrapeResult,
+) -> sqlx::Result<()> {
+    let (status, failure, message) = match &result.outcome {
+        Ok(status) => (Some(status.as_str()), None, None),
+        Err(err) => (None, Some(err.failure), Some(err.message.as_str())),
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO scrape_results (run_id, user_id, program_id, status, failure, message)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        run_id,
+        result.user_id,
+        result.program_id,
+        status,
+        failure as Option<ScrapeFailure>,
+        message,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn find_credentials(pool: &PgPool) -> sqlx::Result<Vec<Credential>> {
+    sqlx::query_as!(
+        Credential,
+        r#"
+        SELECT
+            user_credentials.user_id,
+            user_credentials.program_id,
+            programs.slug,
+            user_credentials.username,
+            user_credentials.password_ciphertext,
+            user_credentials.data_key,
+            user_credentials.key_version
+        FROM user_credentials
+        INNER JOIN programs
+            ON user_credentials.program_id = programs.id
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Renews the statuses of every credential, one at a time.
+///
+/// A failure on one credential does not stop the others; it is recorded in
+/// `scrape_results` and returned in the report. The run is finished even when
+/// the credentials cannot be listed, so that it does not look like it is still
+/// in progress.
+pub async fn renew_statuses(
+    db_url: &str,
+    registry: &Registry,
+    keyring: &Keyring,
+) -> anyhow::Result<ScrapeReport> {
+    let pool = PgPool::connect(db_url).await?;
+
+    let run_id = sqlx::query_scalar!("INSERT INTO scrape_runs DEFAULT VALUES RETURNING id")
+        .fetch_one(&pool)
+        .await?;
+
+    let credentials = find_credentials(&pool).await;
+
+    let mut results = vec![];
+    for credential in credentials.iter().flatten() {
+        let outcome = renew_status(&pool, registry, keyring, credential).await;
+
+        if let Err(err) = &outcome {
+            tracing::warn!(
+                program_id = credential.program_id,
+                failure = ?err.failure,
+                "{}",
+                err.message
+            );
+        }
+
+        let result = ScrapeResult {
+            user_id: credential.user_id,
+            program_id: credential.program_id,
+            outcome,
+        };
+        // The result is still in the report, so the run goes on.
+        if let Err(err) = record_result(&pool, Some(run_id), &result).await {
+            tracing::error!(
+                program_id = credential.program_id,
+                "Failed to record a scrape result: {}",
+                err
+            );
+        }
+        results.push(result);
+    }
+
+    let report = ScrapeReport { run_id, results };
+
+    sqlx::query!(
+        r#"
+        UPDATE scrape_runs
+        SET
+            finished_at = NOW(),
+            succeeded = $2,
+            failed = $3
+        WHERE id = $1
+        "#,
+        run_id,
+        report.succeeded() as i32,
+        report.failed() as i32,
+    )
+    .execute(&pool)
+    .await?;
+
+    credentials?;
+    Ok(report)
+}
+
+struct StoredPassword {
+    user_id: i32,
+    program_id: i32,
+    password: Option<String>,
+    password_ciphertext: Option<Vec<u8>>,
+    data_key: Option<Vec<u8>>,
+    key_version: Option<i32>,
+}
+
+/// Encrypts plaintext passwords and re-encrypts data keys sealed with an old
+/// master key, so that the old key can be removed from `CREDENTIAL_KEYS`.
+///
+/// Returns the number of updated rows.
+pub async fn rekey(db_url: &str, keyring: &Keyring) -> anyhow::Result<usize> {
+    let pool = PgPool::connect(db_url).await?;
+    let mut trans = pool.begin().await?;
+
+    let rows = sqlx::query_as!(
+        StoredPassword,
+        r#"
+        SELECT
+            user_id,
+            program_id,
+            password,
+            password_ciphertext,
+            data_key,
+            key_version
+        FROM user_credentials
+        WHERE
+            key_version IS NULL
+            OR key_version <> $1
+        FOR UPDATE
+        "#,
+        keyring.current_version(),
+    )
+    .fetch_all(&mut trans)
+    .await?;
+
+    for row in &rows {
+        let sealed = match sealed_password(&row.password_ciphertext, &row.data_key, row.key_version)
+        {
+            Some(sealed) => keyring.rewrap(&sealed)?,
+            None => match &row.password {
+                Some(password) => keyring.encrypt(password)?,
+                None => bail!(
+                    "A credential of program {} has no password.",
+                    row.program_id
+                ),
+            },
         };
 
         sqlx::query!(
             r#"
-            WITH get_level AS (
-                SELECT level
-                FROM program_statuses
-                WHERE
-                    program_id = $2
-                    AND name = $3
-            )
-            INSERT INTO user_statuses
-            VALUES (
-                $1,
-                $2,
-                (SELECT * FROM get_level)
-            )
-            ON CONFLICT (user_pubkey, program_id)
-            DO UPDATE
-                SET level = (SELECT * FROM get_level)
+            UPDATE user_credentials
+            SET
+                password = NULL,
+                password_ciphertext = $3,
+                data_key = $4,
+                key_version = $5
+            WHERE
+                user_id = $1
+                AND program_id = $2
             "#,
-            &credential.user_pubkey,
-            credential.program_id,
-            status,
+            row.user_id,
+            row.program_id,
+            &sealed.ciphertext,
+            &sealed.data_key,
+            sealed.key_version,
+        )
+        .execute(&mut trans)
+        .await?;
+    }
+
+    trans.commit().await?;
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use headless_chrome::Tab;
+
+    use super::*;
+
+    const KEY: &str = "1:jiKFIbqQcLSfnepaD0hNF7O4UjJMK863qxcGe9zZU8o=";
+
+    /// Returns `outcome` after `delay` instead of driving Chrome.
+    struct Fake {
+        delay: Duration,
+        outcome: fn() -> Result<String, ScrapeError>,
+    }
+
+    impl ProgramScraper for Fake {
+        fn slug(&self) -> &'static str {
+            "fake"
+        }
+
+        fn login(&self, _: &Tab, _: &str, _: &str) -> Result<(), ScrapeError> {
+            unreachable!()
+        }
+
+        fn fetch_status(&self, _: &Tab) -> Result<String, ScrapeError> {
+            unreachable!()
+        }
+
+        fn translate_status(&self, status: &str) -> Result<String, ScrapeError> {
+            Ok(status.to_string())
+        }
+
+        fn retrieve_status(&self, _: &str, _: &str) -> Result<String, ScrapeError> {
+            std::thread::sleep(self.delay);
+            (self.outcome)()
+        }
+    }
+
+    fn fake(outcome: fn() -> Result<String, ScrapeError>) -> Fake {
+        Fake {
+            delay: Duration::ZERO,
+            outcome,
+        }
+    }
+
+    async fn failure_of(scraper: Fake, timeout: Duration) -> ScrapeFailure {
+        retrieve_status_within(
+            Arc::new(scraper),
+            "user".into(),
+            "password".into(),
+            (),
+            timeout,
         )
-        .fetch_optional(&pool)
         .await
-        .unwrap();
+        .unwrap_err()
+        .failure
+    }
+
+    fn credential(slug: &str, sealed: Option<Sealed>) -> Credential {
+        Credential {
+            user_id: 1,
+            program_id: 1,
+            slug: Some(slug.to_string()),
+            username: "user".to_string(),
+            password_ciphertext: sealed.as_ref().map(|s| s.ciphertext.clone()),
+            data_key: sealed.as_ref().map(|s| s.data_key.clone()),
+            key_version: sealed.as_ref().map(|s| s.key_version),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_hold_guard_until_scraper_returns() {
+        let scraper = Fake {
+            delay: Duration::from_millis(200),
+            outcome: || Ok("Gold".to_string()),
+        };
+        let guard = Arc::new(());
+        let result = retrieve_status_within(
+            Arc::new(scraper),
+            "user".into(),
+            "password".into(),
+            guard.clone(),
+            Duration::from_millis(10),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(2, Arc::strong_count(&guard));
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(1, Arc::strong_count(&guard));
+    }
+
+    #[tokio::test]
+    async fn should_classify_slow_scraper_as_timeout() {
+        let scraper = Fake {
+            delay: Duration::from_millis(200),
+            outcome: || Ok("Gold".to_string()),
+        };
+        assert_eq!(
+            ScrapeFailure::Timeout,
+            failure_of(scraper, Duration::from_millis(10)).await
+        );
+    }
+
+    #[tokio::test]
+    async fn should_classify_panicking_scraper_as_other() {
+        let scraper = fake(|| panic!("The scraper panicked."));
+        assert_eq!(
+            ScrapeFailure::Other,
+            failure_of(scraper, SCRAPE_TIMEOUT).await
+        );
+    }
+
+    #[tokio::test]
+    async fn should_keep_classification_of_scraper() {
+        let scraper = fake(|| Err(ScrapeError::new(ScrapeFailure::BadCredentials, "")));
+        assert_eq!(
+            ScrapeFailure::BadCredentials,
+            failure_of(scraper, SCRAPE_TIMEOUT).await
+        );
+    }
+
+    #[tokio::test]
+    async fn should_classify_unregistered_program_as_other() {
+        let keyring = Keyring::parse(KEY).unwrap();
+        let sealed = keyring.encrypt("password").unwrap();
+
+        let err = scrape(
+            &Registry::new(),
+            &keyring,
+            &credential("fake", Some(sealed)),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(ScrapeFailure::Other, err.failure);
+    }
+
+    #[tokio::test]
+    async fn should_classify_unencrypted_password_as_other() {
+        let keyring = Keyring::parse(KEY).unwrap();
+        let mut registry = Registry::new();
+        registry.register(fake(|| Ok("Gold".to_string())));
+
+        let err = scrape(&registry, &keyring, &credential("fake", None))
+            .await
+            .unwrap_err();
+        assert_eq!(ScrapeFailure::Other, err.failure);
+    }
+
+    #[tokio::test]
+    async fn can_scrape_with_decrypted_password() {
+        let keyring = Keyring::parse(KEY).unwrap();
+        let sealed = keyring.encrypt("password").unwrap();
+        let mut registry = Registry::new();
+        registry.register(fake(|| Ok("Gold".to_string())));
+
+        let status = scrape(&registry, &keyring, &credential("fake", Some(sealed)))
+            .await
+            .unwrap();
+        assert_eq!("Gold", status);
     }
 }
@@ -1,10 +1,46 @@
+use anyhow::bail;
 use dotenv::dotenv;
+use scraper::{
+    daemon::{self, DaemonConfig},
+    registry::Registry,
+    vault::Keyring,
+};
 use std::env;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     dotenv().ok();
     tracing_subscriber::fmt::init();
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
-    scraper::renew_statuses(&db_url).await;
+    let keyring = Keyring::from_env()?;
+
+    let args = &env::args().collect::<Vec<_>>()[..];
+    match args {
+        [_] => {
+            let report = scraper::renew_statuses(&db_url, &Registry::default(), &keyring).await?;
+
+            tracing::info!(
+                run_id = report.run_id,
+                succeeded = report.succeeded(),
+                failed = report.failed(),
+                "Statuses have been renewed."
+            );
+            Ok(())
+        }
+        [_, command] if command == "rekey" => {
+            let count = scraper::rekey(&db_url, &keyring).await?;
+
+            tracing::info!(
+                count,
+                key_version = keyring.current_version(),
+                "Passwords have been re-encrypted."
+            );
+            Ok(())
+        }
+        [_, command] if command == "daemon" => {
+            let config = DaemonConfig::from_env()?;
+            daemon::run(&db_url, Registry::default(), keyring, config).await
+        }
+        _ => bail!("[example] scraper | scraper daemon | scraper rekey"),
+    }
 }
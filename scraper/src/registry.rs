@@ -0,0 +1,81 @@
+use std::{collections::HashMap, sync::Arc};
+
+use headless_chrome::{Browser, LaunchOptionsBuilder, Tab};
+
+use crate::{
+    cocoweb::Cocoweb,
+    dormys::Dormys,
+    error::{ScrapeError, ScrapeFailure},
+};
+
+/// A loyalty program whose member status can be scraped from its website.
+///
+/// Implementations are looked up by `slug`, which matches `programs.slug`.
+pub trait ProgramScraper: Send + Sync {
+    fn slug(&self) -> &'static str;
+
+    fn login(&self, tab: &Tab, username: &str, password: &str) -> Result<(), ScrapeError>;
+
+    fn fetch_status(&self, tab: &Tab) -> Result<String, ScrapeError>;
+
+    /// Translates the status text shown on the website into the name stored in `program_statuses`.
+    fn translate_status(&self, status: &str) -> Result<String, ScrapeError>;
+
+    fn retrieve_status(&self, username: &str, password: &str) -> Result<String, ScrapeError> {
+        let launch_options = LaunchOptionsBuilder::default()
+            .sandbox(false)
+            .build()
+            .map_err(|err| ScrapeError::new(ScrapeFailure::Other, err.to_string()))?;
+        let browser = Browser::new(launch_options)?;
+
+        let tab = browser.new_tab()?;
+        tab.enable_stealth_mode()?;
+
+        self.login(&tab, username, password)?;
+        let status = self.fetch_status(&tab)?;
+
+        self.translate_status(&status)
+    }
+}
+
+pub struct Registry {
+    scrapers: HashMap<&'static str, Arc<dyn ProgramScraper>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            scrapers: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, scraper: impl ProgramScraper + 'static) {
+        self.scrapers.insert(scraper.slug(), Arc::new(scraper));
+    }
+
+    pub fn get(&self, slug: &str) -> Option<Arc<dyn ProgramScraper>> {
+        self.scrapers.get(slug).cloned()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(Dormys);
+        registry.register(Cocoweb);
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_find_registered_scrapers() {
+        let registry = Registry::default();
+        assert_eq!(Some("dormys"), registry.get("dormys").map(|s| s.slug()));
+        assert_eq!(Some("cocoweb"), registry.get("cocoweb").map(|s| s.slug()));
+        assert!(registry.get("unknown").is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng},
+    AeadCore, Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+const NONCE_LEN: usize = 12;
+
+/// A password encrypted with its own data key, which is in turn encrypted
+/// with the master key of `key_version`.
+#[derive(Debug, Clone)]
+pub struct Sealed {
+    pub ciphertext: Vec<u8>,
+    pub data_key: Vec<u8>,
+    pub key_version: i32,
+}
+
+/// Master keys read from `CREDENTIAL_KEYS`, e.g. `1:<base64>,2:<base64>`.
+///
+/// The key with the highest version encrypts; every listed key can decrypt.
+pub struct Keyring {
+    keys: BTreeMap<i32, Key<Aes256Gcm>>,
+}
+
+fn seal(key: &Key<Aes256Gcm>, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(key)
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow!("Encryption failed."))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn open(key: &Key<Aes256Gcm>, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        bail!("The ciphertext is too short.");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Decryption failed."))
+}
+
+impl Keyring {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let keys = std::env::var("CREDENTIAL_KEYS").context("CREDENTIAL_KEYS must be set.")?;
+        Self::parse(&keys)
+    }
+
+    pub fn parse(keys: &str) -> anyhow::Result<Self> {
+        let keys = keys
+            .split(',')
+            .map(|entry| {
+                let (version, key) = entry
+                    .trim()
+                    .split_once(':')
+                    .context("A key must be formatted as <version>:<base64>.")?;
+                let key = STANDARD.decode(key)?;
+                if key.len() != 32 {
+                    bail!("Key {} must be 32 bytes.", version);
+                }
+                Ok((version.parse()?, *Key::<Aes256Gcm>::from_slice(&key)))
+            })
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+        if keys.is_empty() {
+            bail!("At least one key is required.");
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn current_version(&self) -> i32 {
+        *self.keys.keys().next_back().unwrap()
+    }
+
+    fn key(&self, version: i32) -> anyhow::Result<&Key<Aes256Gcm>> {
+        self.keys
+            .get(&version)
+            .ok_or_else(|| anyhow!("Key {} is not in the keyring.", version))
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> anyhow::Result<Sealed> {
+        let data_key = Aes256Gcm::generate_key(&mut OsRng);
+        let key_version = self.current_version();
+
+        Ok(Sealed {
+            ciphertext: seal(&data_key, plaintext.as_bytes())?,
+            data_key: seal(self.key(key_version)?, &data_key)?,
+            key_version,
+        })
+    }
+
+    fn open_data_key(&self, sealed: &Sealed) -> anyhow::Result<Key<Aes256Gcm>> {
+        let data_key = open(self.key(sealed.key_version)?, &sealed.data_key)?;
+        if data_key.len() != 32 {
+            bail!("The data key is corrupted.");
+        }
+        Ok(*Key::<Aes256Gcm>::from_slice(&data_key))
+    }
+
+    pub fn decrypt(&self, sealed: &Sealed) -> anyhow::Result<String> {
+        let data_key = self.open_data_key(sealed)?;
+        Ok(String::from_utf8(open(&data_key, &sealed.ciphertext)?)?)
+    }
+
+    /// Re-encrypts the data key with the current master key.
+    ///
+    /// The password itself keeps its ciphertext.
+    pub fn rewrap(&self, sealed: &Sealed) -> anyhow::Result<Sealed> {
+        let data_key = self.open_data_key(sealed)?;
+        let key_version = self.current_version();
+
+        Ok(Sealed {
+            ciphertext: sealed.ciphertext.clone(),
+            data_key: seal(self.key(key_version)?, &data_key)?,
+            key_version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: &str = "1:jiKFIbqQcLSfnepaD0hNF7O4UjJMK863qxcGe9zZU8o=";
+    const NEW_KEY: &str = "2:3q2+7wAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    #[test]
+    fn can_decrypt_encrypted_password() {
+        let keyring = Keyring::parse(OLD_KEY).unwrap();
+        let sealed = keyring.encrypt("password").unwrap();
+        assert_eq!(1, sealed.key_version);
+        assert_eq!("password", keyring.decrypt(&sealed).unwrap());
+    }
+
+    #[test]
+    fn can_rewrap_with_new_key() {
+        let old = Keyring::parse(OLD_KEY).unwrap();
+        let sealed = old.encrypt("password").unwrap();
+
+        let rotated = Keyring::parse(&format!("{},{}", OLD_KEY, NEW_KEY)).unwrap();
+        let rewrapped = rotated.rewrap(&sealed).unwrap();
+        assert_eq!(2, rewrapped.key_version);
+
+        let new = Keyring::parse(NEW_KEY).unwrap();
+        assert_eq!("password", new.decrypt(&rewrapped).unwrap());
+        assert!(new.decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn should_reject_malformed_keys() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("1:c2hvcnQ=").is_err());
+        assert!(Keyring::parse("jiKFIbqQcLSfnepaD0hNF7O4UjJMK863qxcGe9zZU8o=").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Program name matching shared by the backend and the CLI.
+//!
+//! Similarity follows `pg_trgm`, so the backend can narrow candidates down
+//! with a trigram index and rank them the same way the CLI does in memory.
+
+use std::collections::HashSet;
+
+/// The default `pg_trgm.similarity_threshold`, below which `%` does not match.
+pub const SIMILARITY_THRESHOLD: f32 = 0.3;
+
+/// Names that members use for a program, by the name it is listed under.
+pub const ALIASES: &[(&str, &[&str])] = &[
+    ("Marriott Bonvoy", &["Bonvoy", "Marriott", "SPG"]),
+    ("Hilton Honors", &["Hilton", "HHonors"]),
+    ("IHG One Rewards", &["IHG", "IHG Rewards Club"]),
+    ("World of Hyatt", &["Hyatt"]),
+    ("Accor Live Limitless", &["ALL", "Accor"]),
+    ("Wyndham Rewards", &["Wyndham"]),
+    ("Radisson Rewards", &["Radisson"]),
+];
+
+/// The aliases of the program listed under `name`.
+pub fn aliases_of(name: &str) -> &'static [&'static str] {
+    ALIASES
+        .iter()
+        .find(|(program, _)| program.eq_ignore_ascii_case(name))
+        .map_or(&[], |(_, aliases)| aliases)
+}
+
+/// Trigrams of every word, padded as `pg_trgm` does: two spaces before, one after.
+fn trigrams(text: &str) -> HashSet<[char; 3]> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .flat_map(|word| {
+            let padded: Vec<char> = "  "
+                .chars()
+                .chain(word.chars())
+                .chain(" ".chars())
+                .collect();
+            padded
+                .windows(3)
+                .map(|w| [w[0], w[1], w[2]])
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The `similarity()` of `pg_trgm`: shared trigrams over all trigrams.
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    let shared = a.intersection(&b).count();
+    let all = a.len() + b.len() - shared;
+    if all == 0 {
+        return 0.0;
+    }
+    shared as f32 / all as f32
+}
+
+/// How well `query` matches `candidate`, or `None` when it does not match at all.
+///
+/// Exact matches rank first, then prefixes, then substrings, then typos.
+fn score_one(query: &str, candidate: &str) -> Option<f32> {
+    let (query, candidate_lower) = (query.to_lowercase(), candidate.to_lowercase());
+    let similarity = similarity(&query, candidate);
+
+    let boost = if candidate_lower == query {
+        3.0
+    } else if candidate_lower.starts_with(&query) {
+        2.0
+    } else if candidate_lower.contains(&query) {
+        1.0
+    } else if similarity >= SIMILARITY_THRESHOLD {
+        0.0
+    } else {
+        return None;
+    };
+    Some(boost + similarity)
+}
+
+/// The best score of `query` against the name of a program or any of its aliases.
+pub fn score(query: &str, name: &str, aliases: &[&str]) -> Option<f32> {
+    let query = query.trim();
+    if query.is_empty() {
+        return None;
+    }
+
+    std::iter::once(name)
+        .chain(aliases.iter().copied())
+        .filter_map(|candidate| score_one(query, candidate))
+        .max_by(f32::total_cmp)
+}
+
+/// Orders the programs that match `query` from the most relevant, breaking
+/// ties by name so that pages stay stable.
+pub fn rank<T>(
+    query: &str,
+    programs: impl IntoIterator<Item = T>,
+    name: impl Fn(&T) -> &str,
+    aliases: impl Fn(&T) -> Vec<&str>,
+) -> Vec<T> {
+    let mut scored: Vec<_> = programs
+        .into_iter()
+        .filter_map(|program| {
+            let score = score(query, name(&program), &aliases(&program))?;
+            Some((score, program))
+        })
+        .collect();
+
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .total_cmp(a_score)
+            .then_with(|| name(a).cmp(name(b)))
+    });
+    scored.into_iter().map(|(_, program)| program).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &[&str] = &[
+        "Hilton Honors",
+        "Marriott Bonvoy",
+        "IHG One Rewards",
+        "Hilton Grand Vacations",
+    ];
+
+    fn search(query: &str) -> Vec<&'static str> {
+        rank(
+            query,
+            PROGRAMS.iter().copied(),
+            |p| p,
+            |p| aliases_of(p).to_vec(),
+        )
+    }
+
+    #[test]
+    fn should_match_pg_trgm_similarity() {
+        // SELECT similarity('word', 'two words') = 0.36363637
+        assert!((similarity("word", "two words") - 0.363_636_37).abs() < 1e-6);
+        assert_eq!(1.0, similarity("Hilton", "hilton"));
+        assert_eq!(0.0, similarity("", ""));
+    }
+
+    #[test]
+    fn should_rank_exact_and_prefix_matches_first() {
+        assert_eq!(
+            vec!["Hilton Honors", "Hilton Grand Vacations"],
+            search("hilton")
+        );
+        assert_eq!(vec!["IHG One Rewards"], search("ihg"));
+    }
+
+    #[test]
+    fn should_match_aliases() {
+        assert_eq!(vec!["Marriott Bonvoy"], search("Bonvoy"));
+        assert_eq!(vec!["Marriott Bonvoy"], search("spg"));
+    }
+
+    #[test]
+    fn should_tolerate_typos() {
+        assert_eq!(vec!["Marriott Bonvoy"], search("mariott"));
+        assert!(search("zzz").is_empty());
+        assert!(search("  ").is_empty());
+    }
+}